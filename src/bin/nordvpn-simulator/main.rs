@@ -0,0 +1,29 @@
+use nordvpn_viking::nordvpn::simulator::Simulator;
+use std::env;
+use std::io::{self, Read, Write};
+use std::process;
+
+fn main() {
+    let args = env::args_os().skip(1).collect::<Vec<_>>();
+    let mut input = Vec::new();
+
+    // Only registration prompts, so other commands never wait on a terminal.
+    if args.first().is_some_and(|command| command == "register") {
+        io::stdin().read_to_end(&mut input).unwrap_or_else(|error| {
+            eprintln!("nordvpn-simulator: {}", error);
+            process::exit(2);
+        });
+    }
+
+    let output = Simulator::from_env()
+        .run_with_input(args, &input)
+        .unwrap_or_else(|error| {
+            eprintln!("nordvpn-simulator: {}", error);
+            process::exit(2);
+        });
+
+    io::stdout().write_all(&output.stdout).unwrap();
+    io::stderr().write_all(&output.stderr).unwrap();
+
+    process::exit(output.status.code().unwrap_or(1));
+}
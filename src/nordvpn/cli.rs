@@ -1,32 +1,97 @@
-use super::re::{self, RegexError};
+use super::country::Country;
+use super::credentials::CredentialStore;
+use super::executor::{self, block_on, spawn_failure, Runner};
+use super::re::{self, ParseError, Profile, RegexError};
+use super::tokenizer::{Record, Records};
+use super::transcript;
+use super::uptime::Uptime;
 use byte_unit::Byte;
-use chrono::{Duration, NaiveDate};
+use chrono::NaiveDate;
+use ipnet::IpNet;
+use regex::{Captures, Match, Regex};
 use semver::Version;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
 use std::ffi::OsStr;
+use std::fmt;
 use std::net::IpAddr;
 use std::process::{Command, Output};
+use std::str::FromStr;
+use std::sync::RwLock;
 use strum;
 use thiserror::Error;
 
 pub type CliResult<T> = Result<T, CliError>;
 
+static PROFILE: RwLock<Option<(u64, &'static Profile)>> = RwLock::new(None);
+static VERSION: RwLock<Option<Version>> = RwLock::new(None);
+
+thread_local! {
+    static NOTICES: RefCell<Option<Vec<Notice>>> = const { RefCell::new(None) };
+}
+
 #[derive(Debug, Error)]
 pub enum CliError {
     #[error("unable to create command")]
     IoError(#[from] std::io::Error),
     #[error("command terminated unsuccessfully")]
     FailedCommand(Command),
+    #[error("the nordvpn command is not installed or not in PATH")]
+    NotInstalled(Command),
+    #[error("cannot reach the nordvpnd daemon, it may not be running")]
+    DaemonUnreachable(Command),
+    #[error("permission denied, the user may not be in the nordvpn group")]
+    PermissionDenied(Command),
+    #[error("command did not finish before the timeout")]
+    TimedOut(Command),
+    #[error("command was cancelled")]
+    Cancelled(Command),
     #[error("failed to get command output as UTF-8")]
     BadEncoding(#[from] std::string::FromUtf8Error),
     #[error("command output did not match as expected")]
     BadOutput(Command),
-    #[error("a regex pattern failed to match")]
-    RegexError(RegexError, Command),
+    #[error("a regex pattern failed to match: {0}")]
+    RegexError(ParseError, Command),
+    #[error("a matched value could not be parsed: {0}")]
+    ParseError(ParseError, Command),
     #[error("setting does not exist")]
     InvalidSettingName(String),
     #[error("the provided value for a setting is malformed or invalid")]
     InvalidSettingValue(String, Vec<String>),
+    #[error("the login callback URL is malformed or invalid")]
+    InvalidCallbackUrl(String),
+    #[error("the login flow is not awaiting a callback")]
+    LoginNotAwaitingCallback,
+    #[error("the access token was rejected")]
+    InvalidToken,
+    #[error("login did not complete, the account is still logged out")]
+    LoginUnconfirmed(Command),
+    #[error("the whitelist entry is malformed or invalid")]
+    InvalidWhitelistEntry(String),
+    #[error("a group can only be combined with a country or country code")]
+    InvalidConnectTarget(ConnectTarget),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, strum::Display)]
+pub enum Diagnosis {
+    #[strum(serialize = "NordVPN is installed and the daemon is reachable")]
+    Ready,
+    #[strum(serialize = "the nordvpn command is not installed or not in PATH")]
+    NotInstalled,
+    #[strum(
+        serialize = "the nordvpnd daemon is not running, start it with `systemctl start nordvpnd`"
+    )]
+    DaemonUnreachable,
+    #[strum(
+        serialize = "the user is not allowed to use the daemon, add it to the nordvpn group with `usermod -aG nordvpn $USER`"
+    )]
+    PermissionDenied,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Notice {
+    UpdateAvailable(String),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -43,7 +108,7 @@ pub struct Connected {
     pub hostname: String,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ConnectOption {
     Country(String),
     Server(String),
@@ -53,6 +118,18 @@ pub enum ConnectOption {
     CountryCity(String, String),
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectTarget {
+    pub group: Option<String>,
+    pub location: Option<ConnectOption>,
+}
+
+// The autoconnect target as the daemon reports it. A lone name could be a
+// country, city or group, which the output does not tell apart, so the
+// arguments are kept as they were given instead of being guessed at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoconnectTarget(Vec<String>);
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Status {
     pub hostname: String,
@@ -62,25 +139,95 @@ pub struct Status {
     pub technology: Technology,
     pub protocol: Protocol,
     pub transfer: Transfer,
-    pub uptime: Duration,
+    pub uptime: Uptime,
+    pub extra: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PartialStatus {
+    pub hostname: Option<String>,
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub technology: Option<Technology>,
+    pub protocol: Option<Protocol>,
+    pub transfer: Option<Transfer>,
+    pub uptime: Option<Uptime>,
+    pub extra: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting(PartialStatus),
+    Connected(Status),
+    Reconnecting(PartialStatus),
+    Unknown(String),
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, strum::Display, strum::EnumString)]
+#[derive(
+    Debug, Copy, Clone, PartialEq, strum::Display, strum::EnumString, Serialize, Deserialize,
+)]
 #[strum(ascii_case_insensitive)]
 #[strum(serialize_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
 pub enum Technology {
     OpenVpn,
     NordLynx,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, strum::Display, strum::EnumString)]
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    strum::Display,
+    strum::EnumString,
+    Serialize,
+    Deserialize,
+)]
 #[strum(ascii_case_insensitive)]
 #[strum(serialize_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
 pub enum Protocol {
     Tcp,
     Udp,
 }
 
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum LoginFlow {
+    #[default]
+    Start,
+    AwaitingCallback(String),
+    LoggedIn(Account),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Registration {
+    Url(String),
+    // The daemon prompts for the email and password, see `register_with`.
+    CredentialsRequired,
+    Registered,
+    AlreadyLoggedIn,
+    Failed(String),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnectionRating(u8);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Error)]
+#[error("connection rating {0} is not between 1 and 5")]
+pub struct RatingOutOfRange(pub u8);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RateOutcome {
+    Rated,
+    AlreadyRated,
+    NoConnection,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Transfer {
     pub received: Byte,
@@ -88,7 +235,11 @@ pub struct Transfer {
 }
 
 pub fn account() -> CliResult<Option<Account>> {
-    let (command, output, stdout) = command(["nordvpn", "account"])?;
+    block_on(account_on(&Runner::blocking()))
+}
+
+pub(crate) async fn account_on(runner: &Runner) -> CliResult<Option<Account>> {
+    let (command, output, stdout) = command(runner, ["account"]).await?;
 
     if stdout.contains("You are not logged in.") {
         return Ok(None);
@@ -96,40 +247,50 @@ pub fn account() -> CliResult<Option<Account>> {
         return Err(CliError::FailedCommand(command));
     }
 
-    let captures = match re::ACCOUNT.captures(&stdout) {
-        Some(captures) => captures,
-        None => return Err(CliError::RegexError(RegexError::Account, command)),
-    };
+    match parse_account(profile_regex(&stdout, |profile| &profile.account), &stdout) {
+        Ok(account) => Ok(Some(account)),
+        Err(failure) => Err(failure.with(command)),
+    }
+}
 
-    let account = Account {
-        email: match captures.name("email") {
-            Some(email) => email.as_str().to_owned(),
-            None => return Err(CliError::RegexError(RegexError::AccountEmail, command)),
-        },
-        active: match captures.name("active") {
-            Some(active) => active.as_str().to_lowercase() == "active",
-            None => return Err(CliError::RegexError(RegexError::AccountActive, command)),
-        },
-        expires: NaiveDate::parse_from_str(
-            &(|| {
-                Some(format!(
-                    "{}-{:02}-{}",
-                    captures.name("expires_month")?.as_str(),
-                    captures.name("expires_day")?.as_str(),
-                    captures.name("expires_year")?.as_str(),
-                ))
-            })()
-            .ok_or(CliError::RegexError(RegexError::AccountExpires, command))?,
+fn parse_account(regex: &Regex, output: &str) -> Parsed<Account> {
+    let fields = Fields::records(regex, output, RegexError::Account)?;
+    let month = fields.get("expires_month", RegexError::AccountExpires)?;
+    let day = fields.get("expires_day", RegexError::AccountExpires)?;
+    let year = fields.get("expires_year", RegexError::AccountExpires)?;
+
+    Ok(Account {
+        email: fields
+            .get("email", RegexError::AccountEmail)?
+            .as_str()
+            .to_owned(),
+        active: fields
+            .get("active", RegexError::AccountActive)?
+            .as_str()
+            .eq_ignore_ascii_case("active"),
+        expires: match NaiveDate::parse_from_str(
+            &format!("{}-{:0>2}-{}", month.as_str(), day.as_str(), year.as_str()),
             "%b-%d-%Y",
-        )
-        .unwrap(),
-    };
-
-    Ok(Some(account))
+        ) {
+            Ok(expires) => expires,
+            Err(_) => {
+                return Err(Failure::Invalid(ParseError::at(
+                    RegexError::AccountExpires,
+                    output,
+                    month.start(),
+                    year.end() - month.start(),
+                )));
+            }
+        },
+    })
 }
 
 pub fn cities(country: &str) -> CliResult<Vec<String>> {
-    let (command, output, stdout) = command(["nordvpn", "cities", country])?;
+    block_on(cities_on(&Runner::blocking(), country))
+}
+
+pub(crate) async fn cities_on(runner: &Runner, country: &str) -> CliResult<Vec<String>> {
+    let (command, output, stdout) = command(runner, ["cities", country]).await?;
 
     if !output.status.success() {
         return Err(CliError::FailedCommand(command));
@@ -137,54 +298,89 @@ pub fn cities(country: &str) -> CliResult<Vec<String>> {
 
     let cities = match re::parse_list(&stdout) {
         Some(cities) => cities,
-        None => return Err(CliError::RegexError(RegexError::Cities, command)),
+        None => {
+            return Err(mismatch(
+                RegexError::Cities,
+                &stdout,
+                &re::WORD_LIST,
+                command,
+            ))
+        }
     };
 
     Ok(cities)
 }
 
 pub fn connect(option: Option<&ConnectOption>) -> CliResult<Connected> {
-    let mut run = vec!["nordvpn", "connect"];
-
-    if let Some(option) = option {
-        match option {
-            ConnectOption::Country(country) => run.push(country),
-            ConnectOption::Server(server) => run.push(server),
-            ConnectOption::CountryCode(country_code) => run.push(country_code),
-            ConnectOption::City(city) => run.push(city),
-            ConnectOption::Group(group) => run.push(group),
-            ConnectOption::CountryCity(country, city) => {
-                run.push(country);
-                run.push(city);
-            }
-        };
+    block_on(connect_on(&Runner::blocking(), option))
+}
+
+pub(crate) async fn connect_on(
+    runner: &Runner,
+    option: Option<&ConnectOption>,
+) -> CliResult<Connected> {
+    connect_to_on(
+        runner,
+        &ConnectTarget {
+            group: None,
+            location: option.cloned(),
+        },
+    )
+    .await
+}
+
+pub fn connect_to(target: &ConnectTarget) -> CliResult<Connected> {
+    block_on(connect_to_on(&Runner::blocking(), target))
+}
+
+pub(crate) async fn connect_to_on(runner: &Runner, target: &ConnectTarget) -> CliResult<Connected> {
+    target.validate()?;
+
+    let mut run = vec!["connect"];
+
+    if let Some(group) = &target.group {
+        run.push("--group");
+        run.push(group);
     }
 
-    let (command, output, stdout) = command(run)?;
+    if let Some(location) = &target.location {
+        run.extend(location.to_args());
+    }
+
+    let (command, output, stdout) = command(runner, run).await?;
 
     if !output.status.success() {
         return Err(CliError::FailedCommand(command));
     }
 
-    let captures = match re::CONNECT.captures(&stdout) {
-        Some(captures) => captures,
-        None => return Err(CliError::RegexError(RegexError::Connect, command)),
-    };
-
-    let connected = (|| {
-        Some(Connected {
-            country: captures.name("country")?.as_str().to_owned(),
-            server: captures.name("server")?.as_str().parse::<u32>().unwrap(),
-            hostname: captures.name("hostname")?.as_str().to_owned(),
-        })
-    })()
-    .ok_or(CliError::RegexError(RegexError::Connect, command))?;
+    match parse_connected(&stdout) {
+        Ok(connected) => Ok(connected),
+        Err(failure) => Err(failure.with(command)),
+    }
+}
 
-    Ok(connected)
+fn parse_connected(output: &str) -> Parsed<Connected> {
+    let fields = Fields::new(&re::CONNECT, output, RegexError::Connect)?;
+
+    Ok(Connected {
+        country: fields
+            .get("country", RegexError::Connect)?
+            .as_str()
+            .to_owned(),
+        server: fields.parse("server", RegexError::Connect)?,
+        hostname: fields
+            .get("hostname", RegexError::Connect)?
+            .as_str()
+            .to_owned(),
+    })
 }
 
 pub fn countries() -> CliResult<Vec<String>> {
-    let (command, output, stdout) = command(["nordvpn", "countries"])?;
+    block_on(countries_on(&Runner::blocking()))
+}
+
+pub(crate) async fn countries_on(runner: &Runner) -> CliResult<Vec<String>> {
+    let (command, output, stdout) = command(runner, ["countries"]).await?;
 
     if !output.status.success() {
         return Err(CliError::FailedCommand(command));
@@ -192,14 +388,38 @@ pub fn countries() -> CliResult<Vec<String>> {
 
     let countries = match re::parse_list(&stdout) {
         Some(countries) => countries,
-        None => return Err(CliError::RegexError(RegexError::Countries, command)),
+        None => {
+            return Err(mismatch(
+                RegexError::Countries,
+                &stdout,
+                &re::WORD_LIST,
+                command,
+            ));
+        }
     };
 
     Ok(countries)
 }
 
+// Tokens missing from the catalog are left out, `countries()` still has them.
+pub fn known_countries() -> CliResult<Vec<&'static Country>> {
+    block_on(known_countries_on(&Runner::blocking()))
+}
+
+pub(crate) async fn known_countries_on(runner: &Runner) -> CliResult<Vec<&'static Country>> {
+    Ok(countries_on(runner)
+        .await?
+        .iter()
+        .filter_map(|token| Country::from_token(token))
+        .collect())
+}
+
 pub fn disconnect() -> CliResult<bool> {
-    let (command, output, stdout) = command(["nordvpn", "disconnect"])?;
+    block_on(disconnect_on(&Runner::blocking()))
+}
+
+pub(crate) async fn disconnect_on(runner: &Runner) -> CliResult<bool> {
+    let (command, output, stdout) = command(runner, ["disconnect"]).await?;
 
     if !output.status.success() {
         return Err(CliError::FailedCommand(command));
@@ -215,7 +435,11 @@ pub fn disconnect() -> CliResult<bool> {
 }
 
 pub fn groups() -> CliResult<Vec<String>> {
-    let (command, output, stdout) = command(["nordvpn", "groups"])?;
+    block_on(groups_on(&Runner::blocking()))
+}
+
+pub(crate) async fn groups_on(runner: &Runner) -> CliResult<Vec<String>> {
+    let (command, output, stdout) = command(runner, ["groups"]).await?;
 
     if !output.status.success() {
         return Err(CliError::FailedCommand(command));
@@ -223,14 +447,25 @@ pub fn groups() -> CliResult<Vec<String>> {
 
     let groups = match re::parse_list(&stdout) {
         Some(groups) => groups,
-        None => return Err(CliError::RegexError(RegexError::Groups, command)),
+        None => {
+            return Err(mismatch(
+                RegexError::Groups,
+                &stdout,
+                &re::WORD_LIST,
+                command,
+            ))
+        }
     };
 
     Ok(groups)
 }
 
 pub fn login() -> CliResult<Option<String>> {
-    let (command, output, stdout) = command(["nordvpn", "login"])?;
+    block_on(login_on(&Runner::blocking()))
+}
+
+pub(crate) async fn login_on(runner: &Runner) -> CliResult<Option<String>> {
+    let (command, output, stdout) = command(runner, ["login"]).await?;
 
     if stdout.contains("You are already logged in.") {
         return Ok(None);
@@ -238,18 +473,72 @@ pub fn login() -> CliResult<Option<String>> {
         return Err(CliError::FailedCommand(command));
     }
 
-    let captures = match re::LOGIN.captures(&stdout) {
-        Some(captures) => captures,
-        None => return Err(CliError::RegexError(RegexError::Login, command)),
-    };
+    match Fields::new(&re::LOGIN, &stdout, RegexError::Login)
+        .and_then(|fields| fields.get("url", RegexError::Login))
+    {
+        Ok(url) => Ok(Some(url.as_str().to_owned())),
+        Err(failure) => Err(failure.with(command)),
+    }
+}
+
+pub fn login_callback(url: &str) -> CliResult<Account> {
+    block_on(login_callback_on(&Runner::blocking(), url))
+}
+
+pub(crate) async fn login_callback_on(runner: &Runner, url: &str) -> CliResult<Account> {
+    if !re::LOGIN_CALLBACK.is_match(url) {
+        return Err(CliError::InvalidCallbackUrl(url.to_owned()));
+    }
+
+    let (command, output, stdout) = command(runner, ["login", "--callback", url]).await?;
+
+    if stdout.contains("The provided callback URL is invalid.") {
+        return Err(CliError::InvalidCallbackUrl(url.to_owned()));
+    } else if !stdout.contains("You are already logged in.") && !output.status.success() {
+        return Err(CliError::FailedCommand(command));
+    }
+
+    match account_on(runner).await? {
+        Some(account) => Ok(account),
+        None => Err(CliError::LoginUnconfirmed(command)),
+    }
+}
 
-    let url = captures.name("url").unwrap().as_str().to_owned();
+pub fn login_with_token(token: &str) -> CliResult<Account> {
+    block_on(login_with_token_on(&Runner::blocking(), token))
+}
+
+pub(crate) async fn login_with_token_on(runner: &Runner, token: &str) -> CliResult<Account> {
+    let (command, output, stdout) = command(runner, ["login", "--token", token]).await?;
+
+    if re::LOGIN_INVALID_TOKEN.is_match(&stdout) {
+        return Err(CliError::InvalidToken);
+    } else if !stdout.contains("You are already logged in.") && !output.status.success() {
+        return Err(CliError::FailedCommand(command));
+    }
+
+    match account_on(runner).await? {
+        Some(account) => Ok(account),
+        None => Err(CliError::LoginUnconfirmed(command)),
+    }
+}
 
-    Ok(Some(url))
+pub fn login_with_stored_token<S>(store: &S) -> CliResult<Option<Account>>
+where
+    S: CredentialStore + ?Sized,
+{
+    match store.load_token()? {
+        Some(token) => login_with_token(&token).map(Some),
+        None => Ok(None),
+    }
 }
 
 pub fn logout() -> CliResult<bool> {
-    let (command, output, stdout) = command(["nordvpn", "logout"])?;
+    block_on(logout_on(&Runner::blocking()))
+}
+
+pub(crate) async fn logout_on(runner: &Runner) -> CliResult<bool> {
+    let (command, output, stdout) = command(runner, ["logout"]).await?;
 
     if stdout.contains("You are not logged in.") {
         return Ok(false);
@@ -262,16 +551,112 @@ pub fn logout() -> CliResult<bool> {
     Err(CliError::BadOutput(command))
 }
 
-pub fn rate() -> CliResult<()> {
-    todo!();
+pub fn rate(rating: ConnectionRating) -> CliResult<RateOutcome> {
+    block_on(rate_on(&Runner::blocking(), rating))
+}
+
+pub(crate) async fn rate_on(runner: &Runner, rating: ConnectionRating) -> CliResult<RateOutcome> {
+    let (command, output, stdout) = command(runner, ["rate", &rating.to_string()]).await?;
+
+    if stdout.contains("You have already provided a rating") {
+        return Ok(RateOutcome::AlreadyRated);
+    } else if stdout.contains("You have not made a connection") {
+        return Ok(RateOutcome::NoConnection);
+    } else if !output.status.success() {
+        return Err(CliError::FailedCommand(command));
+    } else if stdout.contains("Thank you for your feedback!") {
+        return Ok(RateOutcome::Rated);
+    }
+
+    Err(CliError::BadOutput(command))
+}
+
+pub fn register() -> CliResult<Registration> {
+    block_on(register_on(&Runner::blocking()))
+}
+
+pub(crate) async fn register_on(runner: &Runner) -> CliResult<Registration> {
+    let (command, output, stdout) = command(runner, ["register"]).await?;
+
+    registration(command, output, stdout, false)
+}
+
+// Answers the prompts of daemons that return `Registration::CredentialsRequired`.
+pub fn register_with(email: &str, password: &str) -> CliResult<Registration> {
+    block_on(register_with_on(&Runner::blocking(), email, password))
 }
 
-pub fn register() -> CliResult<()> {
-    todo!();
+pub(crate) async fn register_with_on(
+    runner: &Runner,
+    email: &str,
+    password: &str,
+) -> CliResult<Registration> {
+    let input = format!("{}\n{}\n", email, password);
+    let (command, output, stdout) =
+        command_with_input(runner, ["register"], Some(input.as_bytes())).await?;
+
+    registration(command, output, stdout, true)
+}
+
+fn registration(
+    command: Command,
+    output: Output,
+    stdout: String,
+    answered: bool,
+) -> CliResult<Registration> {
+    if stdout.contains("You are already logged in.") {
+        return Ok(Registration::AlreadyLoggedIn);
+    } else if re::REGISTERED.is_match(&stdout) {
+        return Ok(Registration::Registered);
+    } else if !answered && re::REGISTER_PROMPT.is_match(&stdout) {
+        return Ok(Registration::CredentialsRequired);
+    } else if let Some(captures) = re::REGISTER.captures(&stdout) {
+        let url = match captures.name("url") {
+            Some(url) => url.as_str().trim().to_owned(),
+            None => {
+                return Err(mismatch(
+                    RegexError::Register,
+                    &stdout,
+                    &re::REGISTER,
+                    command,
+                ))
+            }
+        };
+
+        return Ok(Registration::Url(url));
+    } else if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stdout
+            .lines()
+            .chain(stderr.lines())
+            .map(str::trim)
+            .rfind(|line| !line.is_empty());
+
+        return match reason {
+            Some(reason) => Ok(Registration::Failed(reason.to_owned())),
+            None => Err(CliError::FailedCommand(command)),
+        };
+    }
+
+    Err(mismatch(
+        RegexError::Register,
+        &stdout,
+        &re::REGISTER,
+        command,
+    ))
 }
 
 #[deprecated(note = "please use `Settings` methods instead")]
 pub fn set<S, T, V>(setting: S, values: V) -> CliResult<()>
+where
+    S: AsRef<str>,
+    T: AsRef<str>,
+    V: IntoIterator<Item = T>,
+{
+    block_on(set_on(&Runner::blocking(), setting, values))
+}
+
+pub(crate) async fn set_on<S, T, V>(runner: &Runner, setting: S, values: V) -> CliResult<()>
 where
     S: AsRef<str>,
     T: AsRef<str>,
@@ -279,10 +664,12 @@ where
 {
     let values = values.into_iter().collect::<Vec<_>>();
     let (command, output, stdout) = command(
-        ["nordvpn", "set", setting.as_ref()]
+        runner,
+        ["set", setting.as_ref()]
             .into_iter()
             .chain(values.iter().map(AsRef::as_ref)),
-    )?;
+    )
+    .await?;
 
     if stdout.contains("The command you entered is not valid.") {
         return Err(CliError::InvalidSettingValue(
@@ -302,347 +689,1302 @@ where
 }
 
 pub fn settings() -> CliResult<Settings> {
-    let (command, output, stdout) = command(["nordvpn", "settings"])?;
+    block_on(settings_on(&Runner::blocking()))
+}
+
+pub(crate) async fn settings_on(runner: &Runner) -> CliResult<Settings> {
+    let (command, output, stdout) = command(runner, ["settings"]).await?;
 
     if !output.status.success() {
         return Err(CliError::FailedCommand(command));
     }
 
-    let captures = match re::SETTINGS.captures(&stdout) {
-        Some(captures) => captures,
-        None => return Err(CliError::RegexError(RegexError::Status, command)),
-    };
+    match parse_settings(profile_regex(&stdout, |profile| &profile.settings), &stdout) {
+        Ok(settings) => Ok(settings),
+        Err(failure) => Err(failure.with(command)),
+    }
+}
+
+fn parse_settings(regex: &Regex, output: &str) -> Parsed<Settings> {
+    let fields = Fields::records(regex, output, RegexError::Settings)?;
 
     let settings = Settings {
-        technology: match captures.name("technology") {
-            Some(technology) => technology.as_str().parse::<Technology>().unwrap(),
-            None => {
-                return Err(CliError::RegexError(
-                    RegexError::SettingsTechnology,
-                    command,
-                ));
-            }
-        },
-        protocol: captures
-            .name("protocol")
-            .map(|protocol| protocol.as_str().parse::<Protocol>().unwrap()),
-        firewall: match captures.name("firewall") {
-            Some(firewall) => firewall.as_str().to_lowercase() == "enabled",
-            None => return Err(CliError::RegexError(RegexError::SettingsFirewall, command)),
-        },
-        killswitch: match captures.name("killswitch") {
-            Some(killswitch) => killswitch.as_str().to_lowercase() == "enabled",
-            None => {
-                return Err(CliError::RegexError(
-                    RegexError::SettingsKillswitch,
-                    command,
-                ));
-            }
-        },
-        cybersec: match captures.name("cybersec") {
-            Some(cybersec) => cybersec.as_str().to_lowercase() == "enabled",
-            None => return Err(CliError::RegexError(RegexError::SettingsCybersec, command)),
+        technology: fields.parse("technology", RegexError::SettingsTechnology)?,
+        protocol: fields.parse_optional("protocol", RegexError::SettingsProtocol)?,
+        firewall: fields.enabled("firewall", RegexError::SettingsFirewall)?,
+        killswitch: fields.enabled("killswitch", RegexError::SettingsKillswitch)?,
+        cybersec: fields.enabled("cybersec", RegexError::SettingsCybersec)?,
+        obfuscate: match fields.name("obfuscate") {
+            Some(_) => Some(fields.enabled("obfuscate", RegexError::SettingsObfuscate)?),
+            None => None,
         },
-        obfuscate: captures
-            .name("obfuscate")
-            .map(|obfuscate| obfuscate.as_str().to_lowercase() == "enabled"),
-        notify: match captures.name("notify") {
-            Some(notify) => notify.as_str().to_lowercase() == "enabled",
-            None => return Err(CliError::RegexError(RegexError::SettingsNotify, command)),
-        },
-        autoconnect: match captures.name("autoconnect") {
-            Some(autoconnect) => autoconnect.as_str().to_lowercase() == "enabled",
-            None => {
-                return Err(CliError::RegexError(
-                    RegexError::SettingsAutoconnect,
-                    command,
-                ));
-            }
-        },
-        ipv6: match captures.name("ipv6") {
-            Some(ipv6) => ipv6.as_str().to_lowercase() == "enabled",
-            None => return Err(CliError::RegexError(RegexError::SettingsIpv6, command)),
-        },
-        dns: match captures.name("dns_disabled") {
-            Some(disabled) => {
-                if disabled.as_str().to_lowercase() != "disabled" {
-                    return Err(CliError::RegexError(RegexError::SettingsDns, command));
-                }
-
-                None
-            }
+        notify: fields.enabled("notify", RegexError::SettingsNotify)?,
+        autoconnect: fields.enabled("autoconnect", RegexError::SettingsAutoconnect)?,
+        autoconnect_target: fields
+            .name("autoconnect_target")
+            .map(|target| AutoconnectTarget::from_args(target.as_str().split_whitespace()))
+            .filter(|target| !target.args().is_empty()),
+        ipv6: fields.enabled("ipv6", RegexError::SettingsIpv6)?,
+        dns: match fields.name("dns_disabled") {
+            Some(disabled) if disabled.as_str().eq_ignore_ascii_case("disabled") => None,
+            Some(enabled) => return Err(fields.invalid(RegexError::SettingsDns, enabled)),
             None => Some(
                 ["dns_primary", "dns_secondary", "dns_tertiary"]
                     .into_iter()
                     .filter_map(|name| {
-                        captures
-                            .name(name)
-                            .map(|address| address.as_str().parse::<IpAddr>().unwrap())
+                        fields
+                            .parse_optional(name, RegexError::SettingsDns)
+                            .transpose()
                     })
-                    .collect(),
+                    .collect::<Parsed<_>>()?,
             ),
         },
+        whitelist: Whitelist {
+            ports: match fields.name("whitelisted_ports") {
+                Some(ports) => re::WHITELISTED_PORT
+                    .captures_iter(ports.as_str())
+                    .map(|port| {
+                        let invalid = || {
+                            let port = port.get(0).unwrap();
+                            Failure::Invalid(ParseError::at(
+                                RegexError::SettingsWhitelistPorts,
+                                output,
+                                ports.start() + port.start(),
+                                port.len(),
+                            ))
+                        };
+                        let parse_port = |name| match port.name(name) {
+                            Some(value) => value
+                                .as_str()
+                                .parse::<u16>()
+                                .map(Some)
+                                .map_err(|_| invalid()),
+                            None => Ok(None),
+                        };
+
+                        let start = parse_port("start")?.ok_or_else(invalid)?;
+                        let end = parse_port("end")?.unwrap_or(start);
+                        let protocol = match port.name("protocol").map(|protocol| protocol.as_str())
+                        {
+                            Some(protocol) if protocol.contains('|') => None,
+                            Some(protocol) => {
+                                Some(protocol.parse::<Protocol>().map_err(|_| invalid())?)
+                            }
+                            None => return Err(invalid()),
+                        };
+
+                        Ok(WhitelistedPorts {
+                            start,
+                            end,
+                            protocol,
+                        })
+                    })
+                    .collect::<Parsed<HashSet<_>>>()?,
+                None => HashSet::new(),
+            },
+            subnets: match fields.name("whitelisted_subnets") {
+                Some(subnets) => {
+                    let mut parsed = HashSet::new();
+                    let mut rest = subnets.as_str();
+
+                    while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+                        let len = rest[start..]
+                            .find(char::is_whitespace)
+                            .unwrap_or(rest.len() - start);
+                        let subnet = &rest[start..start + len];
+                        let offset = subnets.end() - rest.len() + start;
+
+                        match subnet.parse::<IpNet>() {
+                            Ok(subnet) => parsed.insert(subnet),
+                            Err(_) => {
+                                return Err(Failure::Invalid(ParseError::at(
+                                    RegexError::SettingsWhitelistSubnets,
+                                    output,
+                                    offset,
+                                    len,
+                                )));
+                            }
+                        };
+
+                        rest = &rest[start + len..];
+                    }
+
+                    parsed
+                }
+                None => HashSet::new(),
+            },
+        },
+        extra: fields.extra.clone(),
     };
 
     if settings.technology == Technology::OpenVpn {
         if settings.protocol.is_none() {
-            return Err(CliError::RegexError(RegexError::SettingsProtocol, command));
+            return Err(fields.mismatch(RegexError::SettingsProtocol));
         }
 
         if settings.obfuscate.is_none() {
-            return Err(CliError::RegexError(RegexError::SettingsObfuscate, command));
+            return Err(fields.mismatch(RegexError::SettingsObfuscate));
         }
     }
 
-    if settings.dns.is_some() && settings.dns.as_ref().unwrap().is_empty() {
-        return Err(CliError::RegexError(RegexError::SettingsDns, command));
+    if settings.dns.as_ref().is_some_and(HashSet::is_empty) {
+        return Err(fields.mismatch(RegexError::SettingsDns));
     }
 
     Ok(settings)
 }
 
-pub fn status() -> CliResult<Option<Status>> {
-    let (command, output, stdout) = command(["nordvpn", "status"])?;
+pub fn status() -> CliResult<ConnectionState> {
+    block_on(status_on(&Runner::blocking()))
+}
 
-    if stdout.contains("Disconnected") {
-        return Ok(None);
+pub(crate) async fn status_on(runner: &Runner) -> CliResult<ConnectionState> {
+    let (command, output, stdout) = command(runner, ["status"]).await?;
+    let state = Records::parse(&stdout)
+        .value("Status")
+        .map(str::to_lowercase);
+
+    if state.as_deref() == Some("disconnected") {
+        return Ok(ConnectionState::Disconnected);
     } else if !output.status.success() {
         return Err(CliError::FailedCommand(command));
     }
 
-    let captures = match re::STATUS.captures(&stdout) {
-        Some(captures) => captures,
-        None => return Err(CliError::RegexError(RegexError::Status, command)),
+    let regex = profile_regex(&stdout, |profile| &profile.status);
+    let parsed = match state.as_deref() {
+        Some("connected") => parse_status(regex, &stdout).map(ConnectionState::Connected),
+        Some("connecting") => parse_partial_status(regex, &stdout).map(ConnectionState::Connecting),
+        Some("reconnecting") => {
+            parse_partial_status(regex, &stdout).map(ConnectionState::Reconnecting)
+        }
+        _ => Ok(ConnectionState::Unknown(stdout.clone())),
     };
 
-    let status = Status {
-        hostname: match captures.name("hostname") {
-            Some(hostname) => hostname.as_str().to_owned(),
-            None => return Err(CliError::RegexError(RegexError::StatusHostname, command)),
-        },
-        country: match captures.name("country") {
-            Some(country) => country.as_str().to_owned(),
-            None => return Err(CliError::RegexError(RegexError::StatusCountry, command)),
-        },
-        city: match captures.name("city") {
-            Some(city) => city.as_str().to_owned(),
-            None => return Err(CliError::RegexError(RegexError::StatusCity, command)),
-        },
-        ip: match captures.name("ip") {
-            Some(ip) => ip.as_str().parse::<IpAddr>().unwrap(),
-            None => return Err(CliError::RegexError(RegexError::StatusIp, command)),
-        },
-        technology: match captures.name("technology") {
-            Some(technology) => technology.as_str().parse::<Technology>().unwrap(),
-            None => return Err(CliError::RegexError(RegexError::StatusTechnology, command)),
-        },
-        protocol: match captures.name("protocol") {
-            Some(protocol) => protocol.as_str().parse::<Protocol>().unwrap(),
-            None => return Err(CliError::RegexError(RegexError::StatusProtocol, command)),
-        },
-        transfer: (|| {
-            Some(Transfer {
-                received: captures
-                    .name("transfer_received")?
-                    .as_str()
-                    .parse::<Byte>()
-                    .unwrap(),
-                sent: captures
-                    .name("transfer_sent")?
-                    .as_str()
-                    .parse::<Byte>()
-                    .unwrap(),
-            })
-        })()
-        .ok_or(CliError::RegexError(RegexError::StatusTransfer, command))?,
-        uptime: {
-            let years = captures
-                .name("uptime_years")
-                .map_or(0_f64, |value| value.as_str().parse::<f64>().unwrap());
-            let months = captures
-                .name("uptime_months")
-                .map_or(0_f64, |value| value.as_str().parse::<f64>().unwrap());
-            let days = captures
-                .name("uptime_days")
-                .map_or(0_f64, |value| value.as_str().parse::<f64>().unwrap());
-            let hours = captures
-                .name("uptime_hours")
-                .map_or(0_f64, |value| value.as_str().parse::<f64>().unwrap());
-            let minutes = captures
-                .name("uptime_minutes")
-                .map_or(0_f64, |value| value.as_str().parse::<f64>().unwrap());
-            let seconds = captures
-                .name("uptime_seconds")
-                .map_or(0_f64, |value| value.as_str().parse::<f64>().unwrap());
-
-            Duration::milliseconds(
-                (100_f64
-                    * (seconds
-                        + minutes * 60_f64
-                        + hours * 3600_f64
-                        + days * 86400_f64
-                        + months * (2.628_f64 * 10_f64.powi(6))
-                        + years * (3.154_f64 * 10_f64.powi(7))))
-                .round() as i64,
-            )
-        },
+    match parsed {
+        Ok(state) => Ok(state),
+        Err(failure) => Err(failure.with(command)),
+    }
+}
+
+fn parse_partial_status(regex: &Regex, output: &str) -> Parsed<PartialStatus> {
+    partial_status(&Fields::records(regex, output, RegexError::Status)?)
+}
+
+fn partial_status(fields: &Fields) -> Parsed<PartialStatus> {
+    let text = |name| fields.name(name).map(|value| value.as_str().to_owned());
+    let uptime = [
+        "uptime_years",
+        "uptime_months",
+        "uptime_days",
+        "uptime_hours",
+        "uptime_minutes",
+        "uptime_seconds",
+    ]
+    .map(|name| fields.parse_optional::<u64>(name, RegexError::StatusUptime));
+
+    let uptime = match uptime {
+        [Ok(None), Ok(None), Ok(None), Ok(None), Ok(None), Ok(None)] => None,
+        [years, months, days, hours, minutes, seconds] => Some(Uptime {
+            years: years?.unwrap_or_default(),
+            months: months?.unwrap_or_default(),
+            days: days?.unwrap_or_default(),
+            hours: hours?.unwrap_or_default(),
+            minutes: minutes?.unwrap_or_default(),
+            seconds: seconds?.unwrap_or_default(),
+        }),
     };
 
-    Ok(Some(status))
+    Ok(PartialStatus {
+        hostname: text("hostname"),
+        country: text("country"),
+        city: text("city"),
+        ip: fields.parse_optional("ip", RegexError::StatusIp)?,
+        technology: fields.parse_optional("technology", RegexError::StatusTechnology)?,
+        protocol: fields.parse_optional("protocol", RegexError::StatusProtocol)?,
+        transfer: match (
+            fields.parse_optional("transfer_received", RegexError::StatusTransfer)?,
+            fields.parse_optional("transfer_sent", RegexError::StatusTransfer)?,
+        ) {
+            (Some(received), Some(sent)) => Some(Transfer { received, sent }),
+            _ => None,
+        },
+        uptime,
+        extra: fields.extra.clone(),
+    })
+}
+
+fn parse_status(regex: &Regex, output: &str) -> Parsed<Status> {
+    let fields = Fields::records(regex, output, RegexError::Status)?;
+    let partial = partial_status(&fields)?;
+
+    Ok(Status {
+        hostname: partial
+            .hostname
+            .ok_or_else(|| fields.mismatch(RegexError::StatusHostname))?,
+        country: partial
+            .country
+            .ok_or_else(|| fields.mismatch(RegexError::StatusCountry))?,
+        city: partial
+            .city
+            .ok_or_else(|| fields.mismatch(RegexError::StatusCity))?,
+        ip: partial
+            .ip
+            .ok_or_else(|| fields.mismatch(RegexError::StatusIp))?,
+        technology: partial
+            .technology
+            .ok_or_else(|| fields.mismatch(RegexError::StatusTechnology))?,
+        protocol: partial
+            .protocol
+            .ok_or_else(|| fields.mismatch(RegexError::StatusProtocol))?,
+        transfer: partial
+            .transfer
+            .ok_or_else(|| fields.mismatch(RegexError::StatusTransfer))?,
+        uptime: partial.uptime.unwrap_or_default(),
+        extra: partial.extra,
+    })
+}
+
+impl ConnectionState {
+    pub fn is_connected(&self) -> bool {
+        matches!(self, ConnectionState::Connected(_))
+    }
+
+    pub fn status(&self) -> Option<&Status> {
+        match self {
+            ConnectionState::Connected(status) => Some(status),
+            _ => None,
+        }
+    }
+
+    pub fn into_status(self) -> Option<Status> {
+        match self {
+            ConnectionState::Connected(status) => Some(status),
+            _ => None,
+        }
+    }
+}
+
+pub fn whitelist<S, I>(args: I) -> CliResult<bool>
+where
+    S: AsRef<str>,
+    I: IntoIterator<Item = S>,
+{
+    block_on(whitelist_on(&Runner::blocking(), args))
 }
 
-pub fn whitelist() -> CliResult<()> {
-    todo!();
+pub(crate) async fn whitelist_on<S, I>(runner: &Runner, args: I) -> CliResult<bool>
+where
+    S: AsRef<str>,
+    I: IntoIterator<Item = S>,
+{
+    let args = args
+        .into_iter()
+        .map(|arg| arg.as_ref().to_owned())
+        .collect::<Vec<_>>();
+    let (command, output, stdout) = command(
+        runner,
+        ["whitelist"]
+            .into_iter()
+            .chain(args.iter().map(String::as_str)),
+    )
+    .await?;
+
+    if stdout.contains("The command you entered is not valid.")
+        || re::WHITELIST_OUT_OF_RANGE.is_match(&stdout)
+    {
+        return Err(CliError::InvalidWhitelistEntry(args.join(" ")));
+    } else if stdout.contains("already whitelisted.") || stdout.contains("not whitelisted.") {
+        return Ok(false);
+    } else if stdout.contains("successfully.") {
+        return Ok(true);
+    } else if !output.status.success() {
+        return Err(CliError::FailedCommand(command));
+    }
+
+    Err(CliError::BadOutput(command))
 }
 
 pub fn version() -> CliResult<Version> {
-    let (command, output, stdout) = command(["nordvpn", "version"])?;
+    block_on(version_on(&Runner::blocking()))
+}
+
+pub(crate) async fn version_on(runner: &Runner) -> CliResult<Version> {
+    let (command, output, stdout) = command(runner, ["version"]).await?;
 
     if !output.status.success() {
         return Err(CliError::FailedCommand(command));
     }
 
-    let captures = match re::VERSION.captures(&stdout) {
-        Some(captures) => captures,
-        None => return Err(CliError::RegexError(RegexError::Version, command)),
+    let version = match Fields::new(&re::VERSION, &stdout, RegexError::Version)
+        .and_then(|fields| fields.parse::<Version>("version", RegexError::Version))
+    {
+        Ok(version) => version,
+        Err(failure) => return Err(failure.with(command)),
     };
 
-    let version = captures
-        .name("version")
-        .unwrap()
-        .as_str()
-        .parse::<Version>()
-        .unwrap();
+    *PROFILE.write().unwrap() = Some((executor::generation(), Profile::for_version(&version)));
 
     Ok(version)
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Settings {
-    pub technology: Technology,
-    pub protocol: Option<Protocol>,
-    pub firewall: bool,
-    pub killswitch: bool,
-    pub cybersec: bool,
-    pub obfuscate: Option<bool>,
-    pub notify: bool,
-    pub autoconnect: bool,
-    pub ipv6: bool,
-    pub dns: Option<HashSet<IpAddr>>,
+// Output is parsed for the version given here, if any, instead of the one
+// last reported by `version()`.
+pub fn set_version(version: Option<Version>) {
+    *VERSION.write().unwrap() = version;
 }
 
-#[allow(deprecated)]
-impl Settings {
-    pub fn update(&mut self) -> CliResult<()> {
-        self.set_technology(self.technology)?;
-        if let Some(protocol) = self.protocol {
-            self.set_protocol(protocol)?;
-        }
-        self.set_firewall(self.firewall)?;
-        self.set_killswitch(self.killswitch)?;
-        self.set_cybersec(self.cybersec)?;
-        if let Some(obfuscate) = self.obfuscate {
-            self.set_obfuscate(obfuscate)?;
-        }
-        self.set_notify(self.notify)?;
-        self.set_autoconnect(self.autoconnect)?;
-        self.set_ipv6(self.ipv6)?;
-        self.set_dns(self.dns.clone())?;
-
-        Ok(())
+// The profile for the version set explicitly, or else for the one that the
+// current executor last reported. The daemon is never asked just for this.
+pub fn profile() -> Option<&'static Profile> {
+    if let Some(version) = &*VERSION.read().unwrap() {
+        return Some(Profile::for_version(version));
     }
 
-    pub fn set_technology(&mut self, technology: Technology) -> CliResult<&mut Self> {
-        set("technology", [technology.to_string().as_str()])?;
-        self.technology = technology;
-        Ok(self)
+    match *PROFILE.read().unwrap() {
+        Some((generation, profile)) if generation == executor::generation() => Some(profile),
+        _ => None,
     }
+}
 
-    pub fn set_protocol(&mut self, protocol: Protocol) -> CliResult<&mut Self> {
-        set("protocol", [protocol.to_string().as_str()])?;
-        self.protocol = Some(protocol);
-        Ok(self)
+// Without a known version, the profile whose regex recognizes the most records
+// of the output is used, and the newest of those on a tie.
+fn profile_regex(output: &str, regex: fn(&'static Profile) -> &'static Regex) -> &'static Regex {
+    if let Some(profile) = profile() {
+        return regex(profile);
     }
 
-    pub fn set_firewall(&mut self, enabled: bool) -> CliResult<&mut Self> {
-        set("firewall", [enabled.to_string().as_str()])?;
-        self.firewall = enabled;
-        Ok(self)
-    }
+    let records = Records::parse(output);
+    let recognized = |regex: &Regex| {
+        records
+            .iter()
+            .filter(|record| match_record(regex, output, record).is_some())
+            .count()
+    };
 
-    pub fn set_killswitch(&mut self, enabled: bool) -> CliResult<&mut Self> {
-        set("killswitch", [enabled.to_string().as_str()])?;
-        self.killswitch = enabled;
-        Ok(self)
-    }
+    re::PROFILES
+        .iter()
+        .map(regex)
+        .max_by_key(|regex| recognized(regex))
+        .unwrap()
+}
+
+// Matches a single record, anchored at its start and cut off at its end.
+fn match_record<'t>(regex: &Regex, output: &'t str, record: &Record) -> Option<Captures<'t>> {
+    regex
+        .captures_at(&output[..record.end], record.start)
+        .filter(|found| found.get(0).unwrap().start() == record.start)
+}
+
+impl ConnectionRating {
+    pub const MIN: Self = Self(1);
+    pub const MAX: Self = Self(5);
+
+    pub fn new(rating: u8) -> Option<Self> {
+        (Self::MIN.0..=Self::MAX.0)
+            .contains(&rating)
+            .then_some(Self(rating))
+    }
+
+    pub fn get(self) -> u8 {
+        self.0
+    }
+}
+
+impl fmt::Display for ConnectionRating {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl TryFrom<u8> for ConnectionRating {
+    type Error = RatingOutOfRange;
+
+    fn try_from(rating: u8) -> Result<Self, Self::Error> {
+        Self::new(rating).ok_or(RatingOutOfRange(rating))
+    }
+}
+
+impl LoginFlow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn begin(&mut self) -> CliResult<&Self> {
+        block_on(self.begin_on(&Runner::blocking()))
+    }
+
+    pub(crate) async fn begin_on(&mut self, runner: &Runner) -> CliResult<&Self> {
+        *self = match login_on(runner).await? {
+            Some(url) => Self::AwaitingCallback(url),
+            None => match account_on(runner).await? {
+                Some(account) => Self::LoggedIn(account),
+                None => Self::Start,
+            },
+        };
+
+        Ok(self)
+    }
+
+    pub fn complete(&mut self, callback: &str) -> CliResult<&Account> {
+        block_on(self.complete_on(&Runner::blocking(), callback))
+    }
+
+    pub(crate) async fn complete_on(
+        &mut self,
+        runner: &Runner,
+        callback: &str,
+    ) -> CliResult<&Account> {
+        let Self::AwaitingCallback(_) = self else {
+            return Err(CliError::LoginNotAwaitingCallback);
+        };
+
+        *self = Self::LoggedIn(login_callback_on(runner, callback).await?);
+
+        Ok(self.account().unwrap())
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            Self::AwaitingCallback(url) => Some(url),
+            _ => None,
+        }
+    }
+
+    pub fn account(&self) -> Option<&Account> {
+        match self {
+            Self::LoggedIn(account) => Some(account),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub technology: Technology,
+    pub protocol: Option<Protocol>,
+    pub firewall: bool,
+    pub killswitch: bool,
+    pub cybersec: bool,
+    pub obfuscate: Option<bool>,
+    pub notify: bool,
+    pub autoconnect: bool,
+    pub autoconnect_target: Option<AutoconnectTarget>,
+    pub ipv6: bool,
+    pub dns: Option<HashSet<IpAddr>>,
+    pub whitelist: Whitelist,
+    pub extra: BTreeMap<String, String>,
+}
+
+impl ConnectOption {
+    pub fn country(&self) -> Option<&'static Country> {
+        match self {
+            ConnectOption::Country(country) | ConnectOption::CountryCity(country, _) => {
+                Country::from_token(country)
+            }
+            ConnectOption::CountryCode(country_code) => Country::from_code(country_code),
+            _ => None,
+        }
+    }
+
+    fn to_args(&self) -> Vec<&str> {
+        match self {
+            ConnectOption::Country(country) => vec![country],
+            ConnectOption::Server(server) => vec![server],
+            ConnectOption::CountryCode(country_code) => vec![country_code],
+            ConnectOption::City(city) => vec![city],
+            ConnectOption::Group(group) => vec![group],
+            ConnectOption::CountryCity(country, city) => vec![country, city],
+        }
+    }
+}
+
+impl AutoconnectTarget {
+    fn from_args<'a, I: IntoIterator<Item = &'a str>>(args: I) -> Self {
+        Self(args.into_iter().map(str::to_owned).collect())
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl From<&ConnectOption> for AutoconnectTarget {
+    fn from(option: &ConnectOption) -> Self {
+        Self::from_args(option.to_args())
+    }
+}
+
+impl From<ConnectOption> for AutoconnectTarget {
+    fn from(option: ConnectOption) -> Self {
+        Self::from(&option)
+    }
+}
+
+impl ConnectTarget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn group<S: Into<String>>(mut self, group: S) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
+    pub fn location(mut self, location: ConnectOption) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    pub fn country<S: Into<String>>(self, country: S) -> Self {
+        self.location(ConnectOption::Country(country.into()))
+    }
+
+    pub fn country_code<S: Into<String>>(self, country_code: S) -> Self {
+        self.location(ConnectOption::CountryCode(country_code.into()))
+    }
+
+    pub fn city<S: Into<String>>(self, city: S) -> Self {
+        self.location(ConnectOption::City(city.into()))
+    }
+
+    pub fn server<S: Into<String>>(self, server: S) -> Self {
+        self.location(ConnectOption::Server(server.into()))
+    }
+
+    pub fn validate(&self) -> CliResult<()> {
+        match (&self.group, &self.location) {
+            (None, _)
+            | (Some(_), None)
+            | (Some(_), Some(ConnectOption::Country(_)))
+            | (Some(_), Some(ConnectOption::CountryCode(_))) => Ok(()),
+            (Some(_), Some(_)) => Err(CliError::InvalidConnectTarget(self.clone())),
+        }
+    }
+}
+
+impl From<&Country> for ConnectOption {
+    fn from(country: &Country) -> Self {
+        ConnectOption::CountryCode(country.code.to_ascii_lowercase())
+    }
+}
+
+impl From<ConnectOption> for ConnectTarget {
+    fn from(location: ConnectOption) -> Self {
+        Self::new().location(location)
+    }
+}
+
+impl Settings {
+    pub fn update(&mut self) -> CliResult<()> {
+        block_on(self.update_on(&Runner::blocking()))
+    }
+
+    pub(crate) async fn update_on(&mut self, runner: &Runner) -> CliResult<()> {
+        self.set_technology_on(runner, self.technology).await?;
+        if let Some(protocol) = self.protocol {
+            self.set_protocol_on(runner, protocol).await?;
+        }
+        self.set_firewall_on(runner, self.firewall).await?;
+        self.set_killswitch_on(runner, self.killswitch).await?;
+        self.set_cybersec_on(runner, self.cybersec).await?;
+        if let Some(obfuscate) = self.obfuscate {
+            self.set_obfuscate_on(runner, obfuscate).await?;
+        }
+        self.set_notify_on(runner, self.notify).await?;
+        match (self.autoconnect, self.autoconnect_target.clone()) {
+            (true, Some(target)) => self.set_autoconnect_to_on(runner, target).await?,
+            (enabled, _) => self.set_autoconnect_on(runner, enabled).await?,
+        };
+        self.set_ipv6_on(runner, self.ipv6).await?;
+        self.set_dns_on(runner, self.dns.clone()).await?;
+        self.whitelist.update_on(runner).await?;
+
+        Ok(())
+    }
+
+    pub fn set_technology(&mut self, technology: Technology) -> CliResult<&mut Self> {
+        block_on(self.set_technology_on(&Runner::blocking(), technology))
+    }
+
+    pub(crate) async fn set_technology_on(
+        &mut self,
+        runner: &Runner,
+        technology: Technology,
+    ) -> CliResult<&mut Self> {
+        set_on(runner, "technology", [technology.to_string().as_str()]).await?;
+        self.technology = technology;
+        Ok(self)
+    }
+
+    pub fn set_protocol(&mut self, protocol: Protocol) -> CliResult<&mut Self> {
+        block_on(self.set_protocol_on(&Runner::blocking(), protocol))
+    }
+
+    pub(crate) async fn set_protocol_on(
+        &mut self,
+        runner: &Runner,
+        protocol: Protocol,
+    ) -> CliResult<&mut Self> {
+        set_on(runner, "protocol", [protocol.to_string().as_str()]).await?;
+        self.protocol = Some(protocol);
+        Ok(self)
+    }
+
+    pub fn set_firewall(&mut self, enabled: bool) -> CliResult<&mut Self> {
+        block_on(self.set_firewall_on(&Runner::blocking(), enabled))
+    }
+
+    pub(crate) async fn set_firewall_on(
+        &mut self,
+        runner: &Runner,
+        enabled: bool,
+    ) -> CliResult<&mut Self> {
+        set_on(runner, "firewall", [enabled.to_string().as_str()]).await?;
+        self.firewall = enabled;
+        Ok(self)
+    }
+
+    pub fn set_killswitch(&mut self, enabled: bool) -> CliResult<&mut Self> {
+        block_on(self.set_killswitch_on(&Runner::blocking(), enabled))
+    }
+
+    pub(crate) async fn set_killswitch_on(
+        &mut self,
+        runner: &Runner,
+        enabled: bool,
+    ) -> CliResult<&mut Self> {
+        set_on(runner, "killswitch", [enabled.to_string().as_str()]).await?;
+        self.killswitch = enabled;
+        Ok(self)
+    }
 
     pub fn set_cybersec(&mut self, enabled: bool) -> CliResult<&mut Self> {
-        set("cybersec", [enabled.to_string().as_str()])?;
-        self.dns = None;
+        block_on(self.set_cybersec_on(&Runner::blocking(), enabled))
+    }
+
+    pub(crate) async fn set_cybersec_on(
+        &mut self,
+        runner: &Runner,
+        enabled: bool,
+    ) -> CliResult<&mut Self> {
+        set_on(runner, "cybersec", [enabled.to_string().as_str()]).await?;
+        if enabled {
+            self.dns = None;
+        }
         self.cybersec = enabled;
         Ok(self)
     }
 
     pub fn set_obfuscate(&mut self, enabled: bool) -> CliResult<&mut Self> {
-        set("obfuscate", [enabled.to_string().as_str()])?;
+        block_on(self.set_obfuscate_on(&Runner::blocking(), enabled))
+    }
+
+    pub(crate) async fn set_obfuscate_on(
+        &mut self,
+        runner: &Runner,
+        enabled: bool,
+    ) -> CliResult<&mut Self> {
+        set_on(runner, "obfuscate", [enabled.to_string().as_str()]).await?;
         self.obfuscate = Some(enabled);
         Ok(self)
     }
 
     pub fn set_notify(&mut self, enabled: bool) -> CliResult<&mut Self> {
-        set("notify", [enabled.to_string().as_str()])?;
+        block_on(self.set_notify_on(&Runner::blocking(), enabled))
+    }
+
+    pub(crate) async fn set_notify_on(
+        &mut self,
+        runner: &Runner,
+        enabled: bool,
+    ) -> CliResult<&mut Self> {
+        set_on(runner, "notify", [enabled.to_string().as_str()]).await?;
         self.notify = enabled;
         Ok(self)
     }
 
     pub fn set_autoconnect(&mut self, enabled: bool) -> CliResult<&mut Self> {
-        set("autoconnect", [enabled.to_string().as_str()])?;
-        self.notify = false;
+        block_on(self.set_autoconnect_on(&Runner::blocking(), enabled))
+    }
+
+    pub(crate) async fn set_autoconnect_on(
+        &mut self,
+        runner: &Runner,
+        enabled: bool,
+    ) -> CliResult<&mut Self> {
+        set_on(runner, "autoconnect", [enabled.to_string().as_str()]).await?;
         self.autoconnect = enabled;
+        self.autoconnect_target = None;
+        Ok(self)
+    }
+
+    pub fn set_autoconnect_to<T: Into<AutoconnectTarget>>(
+        &mut self,
+        target: T,
+    ) -> CliResult<&mut Self> {
+        block_on(self.set_autoconnect_to_on(&Runner::blocking(), target))
+    }
+
+    pub(crate) async fn set_autoconnect_to_on<T: Into<AutoconnectTarget>>(
+        &mut self,
+        runner: &Runner,
+        target: T,
+    ) -> CliResult<&mut Self> {
+        let target = target.into();
+
+        set_on(
+            runner,
+            "autoconnect",
+            std::iter::once("true").chain(target.args().iter().map(String::as_str)),
+        )
+        .await?;
+        self.autoconnect = true;
+        self.autoconnect_target = Some(target);
         Ok(self)
     }
 
     pub fn set_ipv6(&mut self, enabled: bool) -> CliResult<&mut Self> {
-        set("ipv6", [enabled.to_string().as_str()])?;
+        block_on(self.set_ipv6_on(&Runner::blocking(), enabled))
+    }
+
+    pub(crate) async fn set_ipv6_on(
+        &mut self,
+        runner: &Runner,
+        enabled: bool,
+    ) -> CliResult<&mut Self> {
+        set_on(runner, "ipv6", [enabled.to_string().as_str()]).await?;
         self.ipv6 = enabled;
         Ok(self)
     }
 
     pub fn set_dns<V>(&mut self, addresses: Option<V>) -> CliResult<&mut Self>
+    where
+        V: IntoIterator<Item = IpAddr>,
+    {
+        block_on(self.set_dns_on(&Runner::blocking(), addresses))
+    }
+
+    pub(crate) async fn set_dns_on<V>(
+        &mut self,
+        runner: &Runner,
+        addresses: Option<V>,
+    ) -> CliResult<&mut Self>
     where
         V: IntoIterator<Item = IpAddr>,
     {
         if let Some(addresses) = addresses {
             let addresses = addresses.into_iter().collect::<HashSet<_>>();
-            set(
+            set_on(
+                runner,
                 "dns",
                 addresses
                     .clone()
                     .into_iter()
                     .map(|address| address.to_string()),
-            )?;
+            )
+            .await?;
             self.cybersec = false;
             self.dns = Some(addresses);
         } else {
-            set("dns", ["false"])?;
+            set_on(runner, "dns", ["false"]).await?;
             self.dns = None;
         }
         Ok(self)
     }
 }
 
-fn command<S, I>(run: I) -> CliResult<(Command, Output, String)>
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct WhitelistedPorts {
+    pub start: u16,
+    pub end: u16,
+    pub protocol: Option<Protocol>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Whitelist {
+    pub ports: HashSet<WhitelistedPorts>,
+    pub subnets: HashSet<IpNet>,
+}
+
+impl WhitelistedPorts {
+    fn to_args(self) -> Vec<String> {
+        let mut args = vec![self.start.to_string()];
+
+        if self.start != self.end {
+            args.push(self.end.to_string());
+        }
+
+        if let Some(protocol) = self.protocol {
+            args.push("protocol".to_owned());
+            args.push(protocol.to_string());
+        }
+
+        args
+    }
+}
+
+impl Whitelist {
+    pub fn add_port(&mut self, port: u16, protocol: Option<Protocol>) -> CliResult<bool> {
+        block_on(self.add_port_on(&Runner::blocking(), port, protocol))
+    }
+
+    pub(crate) async fn add_port_on(
+        &mut self,
+        runner: &Runner,
+        port: u16,
+        protocol: Option<Protocol>,
+    ) -> CliResult<bool> {
+        self.add_port_range_on(runner, port, port, protocol).await
+    }
+
+    pub fn remove_port(&mut self, port: u16, protocol: Option<Protocol>) -> CliResult<bool> {
+        block_on(self.remove_port_on(&Runner::blocking(), port, protocol))
+    }
+
+    pub(crate) async fn remove_port_on(
+        &mut self,
+        runner: &Runner,
+        port: u16,
+        protocol: Option<Protocol>,
+    ) -> CliResult<bool> {
+        self.remove_port_range_on(runner, port, port, protocol)
+            .await
+    }
+
+    pub fn add_port_range(
+        &mut self,
+        start: u16,
+        end: u16,
+        protocol: Option<Protocol>,
+    ) -> CliResult<bool> {
+        block_on(self.add_port_range_on(&Runner::blocking(), start, end, protocol))
+    }
+
+    pub(crate) async fn add_port_range_on(
+        &mut self,
+        runner: &Runner,
+        start: u16,
+        end: u16,
+        protocol: Option<Protocol>,
+    ) -> CliResult<bool> {
+        let ports = Self::ports(start, end, protocol)?;
+        let kind = if start == end { "port" } else { "ports" };
+        let added = whitelist_on(
+            runner,
+            ["add", kind]
+                .into_iter()
+                .map(str::to_owned)
+                .chain(ports.to_args()),
+        )
+        .await?;
+
+        self.ports.insert(ports);
+
+        Ok(added)
+    }
+
+    pub fn remove_port_range(
+        &mut self,
+        start: u16,
+        end: u16,
+        protocol: Option<Protocol>,
+    ) -> CliResult<bool> {
+        block_on(self.remove_port_range_on(&Runner::blocking(), start, end, protocol))
+    }
+
+    pub(crate) async fn remove_port_range_on(
+        &mut self,
+        runner: &Runner,
+        start: u16,
+        end: u16,
+        protocol: Option<Protocol>,
+    ) -> CliResult<bool> {
+        let ports = Self::ports(start, end, protocol)?;
+        let removed = whitelist_on(
+            runner,
+            ["remove", "port"]
+                .into_iter()
+                .map(str::to_owned)
+                .chain(ports.to_args()),
+        )
+        .await?;
+
+        self.ports.remove(&ports);
+
+        Ok(removed)
+    }
+
+    pub fn add_subnet(&mut self, subnet: IpNet) -> CliResult<bool> {
+        block_on(self.add_subnet_on(&Runner::blocking(), subnet))
+    }
+
+    pub(crate) async fn add_subnet_on(
+        &mut self,
+        runner: &Runner,
+        subnet: IpNet,
+    ) -> CliResult<bool> {
+        let added = whitelist_on(runner, ["add", "subnet", &subnet.to_string()]).await?;
+
+        self.subnets.insert(subnet);
+
+        Ok(added)
+    }
+
+    pub fn remove_subnet(&mut self, subnet: IpNet) -> CliResult<bool> {
+        block_on(self.remove_subnet_on(&Runner::blocking(), subnet))
+    }
+
+    pub(crate) async fn remove_subnet_on(
+        &mut self,
+        runner: &Runner,
+        subnet: IpNet,
+    ) -> CliResult<bool> {
+        let removed = whitelist_on(runner, ["remove", "subnet", &subnet.to_string()]).await?;
+
+        self.subnets.remove(&subnet);
+
+        Ok(removed)
+    }
+
+    pub fn update(&mut self) -> CliResult<()> {
+        block_on(self.update_on(&Runner::blocking()))
+    }
+
+    pub(crate) async fn update_on(&mut self, runner: &Runner) -> CliResult<()> {
+        let current = settings_on(runner).await?.whitelist;
+
+        for ports in current.ports.difference(&self.ports) {
+            whitelist_on(
+                runner,
+                ["remove", "port"]
+                    .into_iter()
+                    .map(str::to_owned)
+                    .chain(ports.to_args()),
+            )
+            .await?;
+        }
+        for subnet in current.subnets.difference(&self.subnets) {
+            whitelist_on(runner, ["remove", "subnet", &subnet.to_string()]).await?;
+        }
+        for ports in self.ports.difference(&current.ports) {
+            let kind = if ports.start == ports.end {
+                "port"
+            } else {
+                "ports"
+            };
+            whitelist_on(
+                runner,
+                ["add", kind]
+                    .into_iter()
+                    .map(str::to_owned)
+                    .chain(ports.to_args()),
+            )
+            .await?;
+        }
+        for subnet in self.subnets.difference(&current.subnets) {
+            whitelist_on(runner, ["add", "subnet", &subnet.to_string()]).await?;
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_all(&mut self) -> CliResult<()> {
+        block_on(self.remove_all_on(&Runner::blocking()))
+    }
+
+    pub(crate) async fn remove_all_on(&mut self, runner: &Runner) -> CliResult<()> {
+        whitelist_on(runner, ["remove", "all"]).await?;
+
+        self.ports.clear();
+        self.subnets.clear();
+
+        Ok(())
+    }
+
+    fn ports(start: u16, end: u16, protocol: Option<Protocol>) -> CliResult<WhitelistedPorts> {
+        if start == 0 || start > end {
+            return Err(CliError::InvalidWhitelistEntry(format!(
+                "{} {}",
+                start, end
+            )));
+        }
+
+        Ok(WhitelistedPorts {
+            start,
+            end,
+            protocol,
+        })
+    }
+}
+
+type Parsed<T> = Result<T, Failure>;
+
+enum Failure {
+    Mismatch(ParseError),
+    Invalid(ParseError),
+}
+
+impl Failure {
+    fn with(self, command: Command) -> CliError {
+        match self {
+            Failure::Mismatch(error) => CliError::RegexError(error, command),
+            Failure::Invalid(error) => CliError::ParseError(error, command),
+        }
+    }
+}
+
+struct Fields<'t> {
+    regex: &'t Regex,
+    output: &'t str,
+    captures: Vec<Captures<'t>>,
+    extra: BTreeMap<String, String>,
+}
+
+impl<'t> Fields<'t> {
+    fn new(regex: &'t Regex, output: &'t str, field: RegexError) -> Parsed<Self> {
+        match regex.captures(output) {
+            Some(captures) => Ok(Self {
+                regex,
+                output,
+                captures: vec![captures],
+                extra: BTreeMap::new(),
+            }),
+            None => Err(Failure::Mismatch(ParseError::mismatch(
+                field, output, regex,
+            ))),
+        }
+    }
+
+    // Matches each record on its own, so that unknown or reordered lines are
+    // kept in `extra` instead of cutting the match short.
+    fn records(regex: &'t Regex, output: &'t str, field: RegexError) -> Parsed<Self> {
+        let mut captures = Vec::new();
+        let mut extra = BTreeMap::new();
+
+        for record in Records::parse(output).iter() {
+            match match_record(regex, output, record) {
+                Some(found) => captures.push(found),
+                None => {
+                    extra.insert(record.key.to_owned(), record.value.to_owned());
+                }
+            }
+        }
+
+        if captures.is_empty() {
+            return Err(Failure::Mismatch(ParseError::mismatch(
+                field, output, regex,
+            )));
+        }
+
+        Ok(Self {
+            regex,
+            output,
+            captures,
+            extra,
+        })
+    }
+
+    fn name(&self, name: &str) -> Option<Match<'t>> {
+        self.captures
+            .iter()
+            .find_map(|captures| captures.name(name))
+    }
+
+    fn mismatch(&self, field: RegexError) -> Failure {
+        Failure::Mismatch(ParseError::mismatch(field, self.output, self.regex))
+    }
+
+    fn invalid(&self, field: RegexError, value: Match) -> Failure {
+        Failure::Invalid(ParseError::invalid(field, self.output, value))
+    }
+
+    fn get(&self, name: &str, field: RegexError) -> Parsed<Match<'t>> {
+        self.name(name).ok_or_else(|| self.mismatch(field))
+    }
+
+    fn parse<T: FromStr>(&self, name: &str, field: RegexError) -> Parsed<T> {
+        let value = self.get(name, field)?;
+
+        value
+            .as_str()
+            .parse::<T>()
+            .map_err(|_| self.invalid(field, value))
+    }
+
+    fn parse_optional<T: FromStr>(&self, name: &str, field: RegexError) -> Parsed<Option<T>> {
+        match self.name(name) {
+            Some(value) => match value.as_str().parse::<T>() {
+                Ok(parsed) => Ok(Some(parsed)),
+                Err(_) => Err(self.invalid(field, value)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn enabled(&self, name: &str, field: RegexError) -> Parsed<bool> {
+        Ok(self
+            .get(name, field)?
+            .as_str()
+            .eq_ignore_ascii_case("enabled"))
+    }
+}
+
+fn mismatch(field: RegexError, output: &str, regex: &Regex, command: Command) -> CliError {
+    CliError::RegexError(ParseError::mismatch(field, output, regex), command)
+}
+
+async fn command<S, I>(runner: &Runner, args: I) -> CliResult<(Command, Output, String)>
 where
     S: AsRef<OsStr>,
     I: IntoIterator<Item = S>,
 {
-    let mut run = run.into_iter();
-    let mut command = Command::new(run.next().unwrap());
+    command_with_input(runner, args, None).await
+}
+
+async fn command_with_input<S, I>(
+    runner: &Runner,
+    args: I,
+    input: Option<&[u8]>,
+) -> CliResult<(Command, Output, String)>
+where
+    S: AsRef<OsStr>,
+    I: IntoIterator<Item = S>,
+{
+    let limits = runner.limits();
+    let mut command = runner.command();
+
+    command.args(args);
+
+    if limits.is_cancelled() {
+        return Err(CliError::Cancelled(transcript::redact(&command)));
+    }
+
+    let result = runner.output(&mut command, input).await;
+    // The command ends up in errors, which must not show a token or password.
+    let command = transcript::redact(&command);
+    let output = match result {
+        Ok(output) => output,
+        // Also reported when an asynchronous executor drops the command, such
+        // as along with the main context that it was running on.
+        Err(error) if limits.is_cancelled() || error.kind() == std::io::ErrorKind::Interrupted => {
+            return Err(CliError::Cancelled(command));
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::TimedOut => {
+            return Err(CliError::TimedOut(command));
+        }
+        Err(error) => {
+            return Err(match spawn_failure(&error) {
+                Some(std::io::ErrorKind::NotFound) => CliError::NotInstalled(command),
+                Some(std::io::ErrorKind::PermissionDenied) => CliError::PermissionDenied(command),
+                _ => error.into(),
+            });
+        }
+    };
+    let (stdout, notices) = normalize(&String::from_utf8(output.stdout.clone())?);
 
-    command.args(run);
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
 
-    let output = command.output()?;
-    let stdout = String::from_utf8(output.stdout.clone())?;
+        if re::DAEMON_UNREACHABLE.is_match(&stdout) || re::DAEMON_UNREACHABLE.is_match(&stderr) {
+            return Err(CliError::DaemonUnreachable(command));
+        } else if re::PERMISSION_DENIED.is_match(&stdout) || re::PERMISSION_DENIED.is_match(&stderr)
+        {
+            return Err(CliError::PermissionDenied(command));
+        }
+    }
+
+    NOTICES.with(|pending| {
+        if let Some(pending) = &mut *pending.borrow_mut() {
+            for notice in notices {
+                if !pending.contains(&notice) {
+                    pending.push(notice);
+                }
+            }
+        }
+    });
 
     Ok((command, output, stdout))
 }
+
+// Drops escape sequences, keeps only what is left visible on each line after
+// carriage return overwrites (spinner frames), and moves advisories aside.
+fn normalize(text: &str) -> (String, Vec<Notice>) {
+    let text = re::ESCAPE_SEQUENCE.replace_all(text, "");
+    let mut normalized = String::with_capacity(text.len());
+    let mut notices = Vec::new();
+
+    for line in text.split_inclusive('\n') {
+        let (line, newline) = match line.strip_suffix('\n') {
+            Some(line) => (line, "\n"),
+            None => (line, ""),
+        };
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let line = line.rsplit('\r').next().unwrap_or(line);
+
+        if re::UPDATE_NOTICE.is_match(line) {
+            notices.push(Notice::UpdateAvailable(line.trim().to_owned()));
+            continue;
+        }
+
+        normalized.push_str(line);
+        normalized.push_str(newline);
+    }
+
+    (normalized, notices)
+}
+
+pub fn diagnose() -> CliResult<Diagnosis> {
+    block_on(diagnose_on(&Runner::blocking()))
+}
+
+pub(crate) async fn diagnose_on(runner: &Runner) -> CliResult<Diagnosis> {
+    match command(runner, ["status"]).await {
+        Ok(_) => Ok(Diagnosis::Ready),
+        Err(CliError::NotInstalled(_)) => Ok(Diagnosis::NotInstalled),
+        Err(CliError::DaemonUnreachable(_)) => Ok(Diagnosis::DaemonUnreachable),
+        Err(CliError::PermissionDenied(_)) => Ok(Diagnosis::PermissionDenied),
+        Err(error) => Err(error),
+    }
+}
+
+// Collects the notices printed by the commands that `run` invokes on this
+// thread. Outside of it they are dropped, so no caller sees another's.
+pub fn with_notices<F, R>(run: F) -> (R, Vec<Notice>)
+where
+    F: FnOnce() -> R,
+{
+    let mut notices = Vec::new();
+    let result = collect_notices(&mut notices, run);
+
+    (result, notices)
+}
+
+// Adds the notices of the commands that `run` invokes on this thread to
+// `notices`, which can be carried over several calls, such as the polls of a
+// future.
+pub(crate) fn collect_notices<F, R>(notices: &mut Vec<Notice>, run: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    struct Restore<'a> {
+        notices: &'a mut Vec<Notice>,
+        previous: Option<Vec<Notice>>,
+    }
+
+    impl Drop for Restore<'_> {
+        fn drop(&mut self) {
+            let previous = self.previous.take();
+            let collected = NOTICES.with(|notices| notices.replace(previous));
+
+            *self.notices = collected.unwrap_or_default();
+        }
+    }
+
+    let collected = std::mem::take(notices);
+    let _restore = Restore {
+        notices,
+        previous: NOTICES.with(|notices| notices.replace(Some(collected))),
+    };
+
+    run()
+}
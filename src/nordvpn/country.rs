@@ -0,0 +1,185 @@
+use std::fmt;
+
+// Matches the `gresources.flag-icons` prefix in `Cargo.toml`.
+pub const FLAGS_PREFIX: &str = "/com/github/spikespaz/nordvpn-viking/flags";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FlagStyle {
+    Square,
+    Rectangle,
+}
+
+// A country as `nordvpn countries` names it, with the ISO 3166-1 alpha-2 code
+// that flag-icons files its flags under.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Country {
+    pub token: &'static str,
+    pub name: &'static str,
+    pub code: &'static str,
+}
+
+pub const COUNTRIES: &[Country] = &[
+    Country::new("Albania", "Albania", "AL"),
+    Country::new("Argentina", "Argentina", "AR"),
+    Country::new("Australia", "Australia", "AU"),
+    Country::new("Austria", "Austria", "AT"),
+    Country::new("Belgium", "Belgium", "BE"),
+    Country::new("Bosnia_And_Herzegovina", "Bosnia and Herzegovina", "BA"),
+    Country::new("Brazil", "Brazil", "BR"),
+    Country::new("Bulgaria", "Bulgaria", "BG"),
+    Country::new("Canada", "Canada", "CA"),
+    Country::new("Chile", "Chile", "CL"),
+    Country::new("Costa_Rica", "Costa Rica", "CR"),
+    Country::new("Croatia", "Croatia", "HR"),
+    Country::new("Cyprus", "Cyprus", "CY"),
+    Country::new("Czech_Republic", "Czech Republic", "CZ"),
+    Country::new("Denmark", "Denmark", "DK"),
+    Country::new("Estonia", "Estonia", "EE"),
+    Country::new("Finland", "Finland", "FI"),
+    Country::new("France", "France", "FR"),
+    Country::new("Georgia", "Georgia", "GE"),
+    Country::new("Germany", "Germany", "DE"),
+    Country::new("Greece", "Greece", "GR"),
+    Country::new("Hong_Kong", "Hong Kong", "HK"),
+    Country::new("Hungary", "Hungary", "HU"),
+    Country::new("Iceland", "Iceland", "IS"),
+    Country::new("India", "India", "IN"),
+    Country::new("Indonesia", "Indonesia", "ID"),
+    Country::new("Ireland", "Ireland", "IE"),
+    Country::new("Israel", "Israel", "IL"),
+    Country::new("Italy", "Italy", "IT"),
+    Country::new("Japan", "Japan", "JP"),
+    Country::new("Latvia", "Latvia", "LV"),
+    Country::new("Lithuania", "Lithuania", "LT"),
+    Country::new("Luxembourg", "Luxembourg", "LU"),
+    Country::new("Malaysia", "Malaysia", "MY"),
+    Country::new("Mexico", "Mexico", "MX"),
+    Country::new("Moldova", "Moldova", "MD"),
+    Country::new("Netherlands", "Netherlands", "NL"),
+    Country::new("New_Zealand", "New Zealand", "NZ"),
+    Country::new("North_Macedonia", "North Macedonia", "MK"),
+    Country::new("Norway", "Norway", "NO"),
+    Country::new("Poland", "Poland", "PL"),
+    Country::new("Portugal", "Portugal", "PT"),
+    Country::new("Romania", "Romania", "RO"),
+    Country::new("Serbia", "Serbia", "RS"),
+    Country::new("Singapore", "Singapore", "SG"),
+    Country::new("Slovakia", "Slovakia", "SK"),
+    Country::new("Slovenia", "Slovenia", "SI"),
+    Country::new("South_Africa", "South Africa", "ZA"),
+    Country::new("South_Korea", "South Korea", "KR"),
+    Country::new("Spain", "Spain", "ES"),
+    Country::new("Sweden", "Sweden", "SE"),
+    Country::new("Switzerland", "Switzerland", "CH"),
+    Country::new("Taiwan", "Taiwan", "TW"),
+    Country::new("Thailand", "Thailand", "TH"),
+    Country::new("Turkey", "Turkey", "TR"),
+    Country::new("Ukraine", "Ukraine", "UA"),
+    Country::new("United_Arab_Emirates", "United Arab Emirates", "AE"),
+    Country::new("United_Kingdom", "United Kingdom", "GB"),
+    Country::new("United_States", "United States", "US"),
+    Country::new("Vietnam", "Vietnam", "VN"),
+];
+
+impl FlagStyle {
+    fn directory(&self) -> &'static str {
+        match self {
+            FlagStyle::Square => "square",
+            FlagStyle::Rectangle => "rectangle",
+        }
+    }
+}
+
+impl Country {
+    const fn new(token: &'static str, name: &'static str, code: &'static str) -> Self {
+        Self { token, name, code }
+    }
+
+    pub fn from_token(token: &str) -> Option<&'static Country> {
+        let token = token.replace(' ', "_");
+
+        COUNTRIES
+            .iter()
+            .find(|country| country.token.eq_ignore_ascii_case(&token))
+    }
+
+    pub fn from_name(name: &str) -> Option<&'static Country> {
+        COUNTRIES
+            .iter()
+            .find(|country| country.name.eq_ignore_ascii_case(name))
+    }
+
+    // The daemon also takes "UK" for the United Kingdom.
+    pub fn from_code(code: &str) -> Option<&'static Country> {
+        let code = if code.eq_ignore_ascii_case("UK") {
+            "GB"
+        } else {
+            code
+        };
+
+        COUNTRIES
+            .iter()
+            .find(|country| country.code.eq_ignore_ascii_case(code))
+    }
+
+    // Tries the token, the display name and then the code.
+    pub fn lookup(any: &str) -> Option<&'static Country> {
+        Self::from_token(any)
+            .or_else(|| Self::from_name(any))
+            .or_else(|| Self::from_code(any))
+    }
+
+    // The alias inside the flag-icons gresource, such as `flags/square/gb.svg`.
+    pub fn flag_path(&self, style: FlagStyle) -> String {
+        format!(
+            "flags/{}/{}.svg",
+            style.directory(),
+            self.code.to_ascii_lowercase()
+        )
+    }
+
+    pub fn flag_resource(&self, style: FlagStyle) -> String {
+        format!("{}/{}", FLAGS_PREFIX, self.flag_path(style))
+    }
+}
+
+impl fmt::Display for Country {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Country, FlagStyle, COUNTRIES};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_countries() {
+        let uk = Country::from_token("United_Kingdom").unwrap();
+        assert_eq!(uk.name, "United Kingdom");
+        assert_eq!(uk.code, "GB");
+        assert_eq!(uk.flag_path(FlagStyle::Square), "flags/square/gb.svg");
+        assert_eq!(
+            uk.flag_resource(FlagStyle::Rectangle),
+            "/com/github/spikespaz/nordvpn-viking/flags/flags/rectangle/gb.svg"
+        );
+
+        assert_eq!(Country::from_code("uk"), Some(uk));
+        assert_eq!(Country::lookup("united kingdom"), Some(uk));
+        assert_eq!(
+            Country::lookup("bosnia and herzegovina").unwrap().token,
+            "Bosnia_And_Herzegovina"
+        );
+        assert_eq!(Country::lookup("Atlantis"), None);
+
+        let codes = COUNTRIES
+            .iter()
+            .map(|country| country.code)
+            .collect::<HashSet<_>>();
+        assert_eq!(codes.len(), COUNTRIES.len());
+        assert!(COUNTRIES
+            .windows(2)
+            .all(|pair| pair[0].token < pair[1].token));
+    }
+}
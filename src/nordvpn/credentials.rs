@@ -0,0 +1,222 @@
+use std::ffi::OsString;
+use std::fmt::Debug;
+use std::io::{self, Write};
+use std::process::{Command, Output, Stdio};
+use std::sync::Mutex;
+
+pub const SERVICE: &str = "nordvpn-viking";
+
+pub trait CredentialStore: Debug + Send + Sync {
+    fn load_token(&self) -> io::Result<Option<String>>;
+
+    fn store_token(&self, token: &str) -> io::Result<()>;
+
+    fn clear_token(&self) -> io::Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    token: Mutex<Option<String>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CredentialStore for MemoryStore {
+    fn load_token(&self) -> io::Result<Option<String>> {
+        Ok(self.token.lock().unwrap().clone())
+    }
+
+    fn store_token(&self, token: &str) -> io::Result<()> {
+        *self.token.lock().unwrap() = Some(token.to_owned());
+        Ok(())
+    }
+
+    fn clear_token(&self) -> io::Result<()> {
+        *self.token.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+// Talks to the freedesktop Secret Service through libsecret's `secret-tool`,
+// so any running implementation (gnome-keyring, KWallet, KeePassXC) will do.
+//
+// This is a runtime dependency: `secret-tool` must be installed (it ships in
+// `libsecret-tools` on Debian and Ubuntu, and in `libsecret` elsewhere), or
+// every method fails with `io::ErrorKind::NotFound`.
+#[derive(Debug, Clone)]
+pub struct SecretServiceStore {
+    pub program: OsString,
+    pub service: String,
+}
+
+impl SecretServiceStore {
+    pub fn new() -> Self {
+        Self::with_program("secret-tool")
+    }
+
+    pub fn with_program<S: Into<OsString>>(program: S) -> Self {
+        Self {
+            program: program.into(),
+            service: SERVICE.to_owned(),
+        }
+    }
+
+    fn command(&self, action: &str) -> Command {
+        let mut command = Command::new(&self.program);
+        command.arg(action);
+
+        if action == "store" {
+            command.arg("--label=NordVPN access token");
+        }
+
+        command.args(["service", &self.service, "kind", "token"]);
+        command
+    }
+
+    fn spawn_error(&self, error: io::Error) -> io::Error {
+        match error.kind() {
+            io::ErrorKind::NotFound => io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{} was not found, install libsecret's secret-tool to store credentials",
+                    self.program.to_string_lossy()
+                ),
+            ),
+            _ => error,
+        }
+    }
+}
+
+impl Default for SecretServiceStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CredentialStore for SecretServiceStore {
+    fn load_token(&self) -> io::Result<Option<String>> {
+        let output = self
+            .command("lookup")
+            .stderr(Stdio::null())
+            .output()
+            .map_err(|error| self.spawn_error(error))?;
+
+        // `secret-tool lookup` exits with 1 and prints nothing when no item matches.
+        if !output.status.success() && output.stdout.is_empty() {
+            return Ok(None);
+        }
+
+        let token = String::from_utf8(output.stdout)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let token = token.trim_end_matches('\n');
+
+        if token.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(token.to_owned()))
+        }
+    }
+
+    fn store_token(&self, token: &str) -> io::Result<()> {
+        let mut child = self
+            .command("store")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|error| self.spawn_error(error))?;
+
+        child.stdin.take().unwrap().write_all(token.as_bytes())?;
+
+        check_status(child.wait_with_output()?)
+    }
+
+    fn clear_token(&self) -> io::Result<()> {
+        check_status(
+            self.command("clear")
+                .output()
+                .map_err(|error| self.spawn_error(error))?,
+        )
+    }
+}
+
+fn check_status(output: Output) -> io::Result<()> {
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ))
+    }
+}
+
+impl<C: CredentialStore + ?Sized> CredentialStore for std::sync::Arc<C> {
+    fn load_token(&self) -> io::Result<Option<String>> {
+        (**self).load_token()
+    }
+
+    fn store_token(&self, token: &str) -> io::Result<()> {
+        (**self).store_token(token)
+    }
+
+    fn clear_token(&self) -> io::Result<()> {
+        (**self).clear_token()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CredentialStore, MemoryStore, SecretServiceStore};
+    use crate::nordvpn::tests::TempDir;
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::PermissionsExt;
+
+    // A stand-in for `secret-tool` that keeps the secret in a file next to itself.
+    const STAND_IN: &str = r#"#!/bin/sh
+secret="$(dirname "$0")/secret"
+case "$1" in
+    store) cat > "$secret" ;;
+    lookup) [ -f "$secret" ] && cat "$secret" || exit 1 ;;
+    clear) rm -f "$secret" ;;
+    *) echo "unknown action $1" >&2; exit 2 ;;
+esac
+"#;
+
+    fn round_trip<C: CredentialStore>(store: &C) {
+        assert_eq!(store.load_token().unwrap(), None);
+
+        store.store_token("0123456789abcdef").unwrap();
+        assert_eq!(store.load_token().unwrap().unwrap(), "0123456789abcdef");
+
+        store.clear_token().unwrap();
+        assert_eq!(store.load_token().unwrap(), None);
+        store.clear_token().unwrap();
+    }
+
+    #[test]
+    fn test_memory_store() {
+        round_trip(&MemoryStore::new());
+    }
+
+    #[test]
+    fn test_secret_service_store() {
+        let temp_dir = TempDir::new("test_secret_service_store");
+
+        let program = temp_dir.path().join("secret-tool");
+        fs::write(&program, STAND_IN).unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+
+        round_trip(&SecretServiceStore::with_program(program));
+
+        let missing = SecretServiceStore::with_program(temp_dir.path().join("missing"));
+        assert_eq!(
+            missing.load_token().unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
@@ -0,0 +1,594 @@
+use super::pool;
+use super::subprocess;
+use once_cell::sync::Lazy;
+use std::cell::RefCell;
+use std::ffi::{OsStr, OsString};
+use std::fmt::Debug;
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::pin::{pin, Pin};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, JoinHandle, Thread};
+use std::time::{Duration, Instant};
+
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+static EXECUTOR: Lazy<RwLock<Arc<dyn Executor>>> =
+    Lazy::new(|| RwLock::new(Arc::new(ProcessExecutor::default())));
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+static TIMEOUT: RwLock<Option<Duration>> = RwLock::new(None);
+
+thread_local! {
+    static LIMITS: RefCell<Limits> = RefCell::new(Limits::default());
+}
+
+pub type OutputFuture = Pin<Box<dyn Future<Output = io::Result<Output>> + Send>>;
+
+pub trait Executor: Debug + Send + Sync {
+    fn command(&self) -> Command {
+        Command::new("nordvpn")
+    }
+
+    fn output(&self, command: &mut Command, limits: &Limits) -> io::Result<Output>;
+
+    // Writes `input` to the standard input of the command, for those that
+    // prompt instead of taking arguments.
+    fn output_with_input(
+        &self,
+        command: &mut Command,
+        input: &[u8],
+        limits: &Limits,
+    ) -> io::Result<Output> {
+        let _ = (command, input, limits);
+
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this executor cannot write to standard input",
+        ))
+    }
+
+    // Runs the command without blocking the caller. Unless overridden, the
+    // methods above are called on a small pool of worker threads.
+    fn output_async(
+        self: Arc<Self>,
+        command: &Command,
+        input: Option<Vec<u8>>,
+        limits: Limits,
+    ) -> OutputFuture
+    where
+        Self: 'static,
+    {
+        let mut command = copy_command(command);
+
+        Box::pin(pool::spawn(move || match input {
+            Some(input) => self.output_with_input(&mut command, &input, &limits),
+            None => self.output(&mut command, &limits),
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub timeout: Option<Duration>,
+    pub cancel: Option<CancelHandle>,
+}
+
+impl Limits {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelHandle::is_cancelled)
+    }
+}
+
+// The error to stop a command with, once it is cancelled or past its deadline.
+pub(crate) fn exceeded(limits: &Limits, deadline: Option<Instant>) -> Option<io::Error> {
+    if limits.is_cancelled() {
+        Some(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "command was cancelled",
+        ))
+    } else if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        Some(io::Error::new(io::ErrorKind::TimedOut, "command timed out"))
+    } else {
+        None
+    }
+}
+
+// Marks the error from starting the binary, so that only `ProcessExecutor`
+// failing to spawn it is taken to mean that it is missing or not executable.
+#[derive(Debug, thiserror::Error)]
+#[error("failed to spawn the nordvpn binary: {0}")]
+struct SpawnError(io::Error);
+
+pub(crate) fn spawn_error(error: io::Error) -> io::Error {
+    io::Error::new(error.kind(), SpawnError(error))
+}
+
+pub(crate) fn spawn_failure(error: &io::Error) -> Option<io::ErrorKind> {
+    error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<SpawnError>())
+        .map(|SpawnError(error)| error.kind())
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcessExecutor {
+    pub program: OsString,
+}
+
+impl ProcessExecutor {
+    pub fn new<S: Into<OsString>>(program: S) -> Self {
+        Self {
+            program: program.into(),
+        }
+    }
+}
+
+impl Default for ProcessExecutor {
+    fn default() -> Self {
+        Self::new("nordvpn")
+    }
+}
+
+impl Executor for ProcessExecutor {
+    fn command(&self) -> Command {
+        Command::new(&self.program)
+    }
+
+    fn output(&self, command: &mut Command, limits: &Limits) -> io::Result<Output> {
+        run(command, None, limits)
+    }
+
+    fn output_with_input(
+        &self,
+        command: &mut Command,
+        input: &[u8],
+        limits: &Limits,
+    ) -> io::Result<Output> {
+        run(command, Some(input), limits)
+    }
+
+    // Within a Tokio runtime (with the `tokio` feature) or on a thread that owns
+    // its GLib main context, the process is awaited there instead of on a
+    // worker thread.
+    fn output_async(
+        self: Arc<Self>,
+        command: &Command,
+        input: Option<Vec<u8>>,
+        limits: Limits,
+    ) -> OutputFuture {
+        let mut command = copy_command(command);
+
+        #[cfg(feature = "tokio")]
+        if tokio::runtime::Handle::try_current().is_ok() {
+            return Box::pin(subprocess::run_tokio(command, input, limits));
+        }
+
+        let context = glib::MainContext::ref_thread_default();
+
+        if context.is_owner() {
+            return Box::pin(subprocess::spawn_local(&context, command, input, limits));
+        }
+
+        Box::pin(pool::spawn(move || {
+            run(&mut command, input.as_deref(), &limits)
+        }))
+    }
+}
+
+fn run(command: &mut Command, input: Option<&[u8]>, limits: &Limits) -> io::Result<Output> {
+    let mut child = command
+        .stdin(match input {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_error)?;
+
+    if let Some(input) = input {
+        write_pipe(child.stdin.take(), input.to_vec());
+    }
+
+    if limits.timeout.is_none() && limits.cancel.is_none() {
+        return child.wait_with_output();
+    }
+
+    let deadline = limits.timeout.map(|timeout| Instant::now() + timeout);
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if let Some(error) = exceeded(limits, deadline) {
+            let _ = child.kill();
+            let _ = child.wait();
+
+            return Err(error);
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap()?,
+        stderr: stderr.join().unwrap()?,
+    })
+}
+
+// A command that exits without reading all of its input closes the pipe early,
+// which is not an error of its own, so the write is left to finish or fail.
+fn write_pipe<W: Write + Send + 'static>(pipe: Option<W>, input: Vec<u8>) {
+    thread::spawn(move || {
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.write_all(&input);
+        }
+    });
+}
+
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut buffer)?;
+        }
+
+        Ok(buffer)
+    })
+}
+
+impl<E: Executor + ?Sized> Executor for Arc<E> {
+    fn command(&self) -> Command {
+        (**self).command()
+    }
+
+    fn output(&self, command: &mut Command, limits: &Limits) -> io::Result<Output> {
+        (**self).output(command, limits)
+    }
+
+    fn output_with_input(
+        &self,
+        command: &mut Command,
+        input: &[u8],
+        limits: &Limits,
+    ) -> io::Result<Output> {
+        (**self).output_with_input(command, input, limits)
+    }
+
+    fn output_async(
+        self: Arc<Self>,
+        command: &Command,
+        input: Option<Vec<u8>>,
+        limits: Limits,
+    ) -> OutputFuture
+    where
+        Self: 'static,
+    {
+        E::output_async(Arc::clone(&*self), command, input, limits)
+    }
+}
+
+// `Command` cannot be cloned, but what an executor sets up on it can be read
+// back, for running it elsewhere.
+fn copy_command(command: &Command) -> Command {
+    copy_command_with_args(command, command.get_args())
+}
+
+// Copies the command with its arguments replaced by `args`.
+pub(crate) fn copy_command_with_args<I, S>(command: &Command, args: I) -> Command
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut copy = Command::new(command.get_program());
+
+    copy.args(args);
+
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => copy.env(key, value),
+            None => copy.env_remove(key),
+        };
+    }
+
+    if let Some(directory) = command.get_current_dir() {
+        copy.current_dir(directory);
+    }
+
+    copy
+}
+
+// What the commands of one call run with, taken from the calling thread when
+// the call is made, so that they hold wherever its future is polled.
+#[derive(Debug, Clone)]
+pub(crate) struct Runner {
+    executor: Arc<dyn Executor>,
+    limits: Limits,
+    blocking: bool,
+}
+
+impl Runner {
+    pub(crate) fn blocking() -> Self {
+        Self {
+            executor: executor(),
+            limits: limits(),
+            blocking: true,
+        }
+    }
+
+    pub(crate) fn nonblocking() -> Self {
+        Self {
+            blocking: false,
+            ..Self::blocking()
+        }
+    }
+
+    pub(crate) fn command(&self) -> Command {
+        self.executor.command()
+    }
+
+    pub(crate) fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub(crate) async fn output(
+        &self,
+        command: &mut Command,
+        input: Option<&[u8]>,
+    ) -> io::Result<Output> {
+        if !self.blocking {
+            return self
+                .executor
+                .clone()
+                .output_async(command, input.map(<[u8]>::to_vec), self.limits.clone())
+                .await;
+        }
+
+        match input {
+            Some(input) => self
+                .executor
+                .output_with_input(command, input, &self.limits),
+            None => self.executor.output(command, &self.limits),
+        }
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// Drives a future on the calling thread until it is ready.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+pub fn executor() -> Arc<dyn Executor> {
+    EXECUTOR.read().unwrap().clone()
+}
+
+pub fn set_executor<E: Executor + 'static>(executor: E) -> Arc<dyn Executor> {
+    let previous = std::mem::replace(&mut *EXECUTOR.write().unwrap(), Arc::new(executor));
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+    previous
+}
+
+// Changes whenever the global executor is replaced, so that what was learned
+// through the previous one can be told apart without keeping it alive.
+pub(crate) fn generation() -> u64 {
+    GENERATION.load(Ordering::SeqCst)
+}
+
+pub fn timeout() -> Option<Duration> {
+    *TIMEOUT.read().unwrap()
+}
+
+pub fn set_timeout(timeout: Option<Duration>) {
+    *TIMEOUT.write().unwrap() = timeout;
+}
+
+pub fn limits() -> Limits {
+    let limits = LIMITS.with(|limits| limits.borrow().clone());
+
+    Limits {
+        timeout: limits.timeout.or_else(timeout),
+        cancel: limits.cancel,
+    }
+}
+
+pub fn with_limits<F, R>(limits: Limits, run: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    struct Restore(Option<Limits>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take().unwrap();
+            LIMITS.with(|limits| *limits.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(Some(LIMITS.with(|current| current.replace(limits))));
+
+    run()
+}
+
+pub fn with_timeout<F, R>(timeout: Duration, run: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let limits = Limits {
+        timeout: Some(timeout),
+        ..LIMITS.with(|limits| limits.borrow().clone())
+    };
+
+    with_limits(limits, run)
+}
+
+pub fn with_cancel<F, R>(cancel: &CancelHandle, run: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let limits = Limits {
+        cancel: Some(cancel.clone()),
+        ..LIMITS.with(|limits| limits.borrow().clone())
+    };
+
+    with_limits(limits, run)
+}
+
+#[cfg(test)]
+pub(crate) fn with_executor<E, F, R>(executor: E, run: F) -> R
+where
+    E: Executor + 'static,
+    F: FnOnce() -> R,
+{
+    use std::sync::Mutex;
+
+    static LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+    let _guard = LOCK.lock().unwrap_or_else(|error| error.into_inner());
+    let previous = set_executor(executor);
+
+    struct Restore(Option<Arc<dyn Executor>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            *EXECUTOR.write().unwrap() = self.0.take().unwrap();
+            GENERATION.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let _restore = Restore(Some(previous));
+
+    run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CancelHandle, Executor, Limits, ProcessExecutor};
+    use crate::nordvpn::{self, CliError};
+    use semver::Version;
+    use std::io;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{Command, ExitStatus, Output};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[derive(Debug, Default)]
+    struct FakeExecutor {
+        args: Mutex<Vec<Vec<String>>>,
+    }
+
+    impl Executor for FakeExecutor {
+        fn output(&self, command: &mut Command, _limits: &Limits) -> io::Result<Output> {
+            let args = command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect();
+
+            self.args.lock().unwrap().push(args);
+
+            Ok(Output {
+                status: ExitStatus::from_raw(0),
+                stdout: b"NordVPN Version 3.12.3\n".to_vec(),
+                stderr: Vec::new(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_injected_executor() {
+        let executor = Arc::new(FakeExecutor::default());
+        let version = super::with_executor(executor.clone(), || crate::nordvpn::version().unwrap());
+
+        assert_eq!(version, Version::new(3, 12, 3));
+        assert_eq!(*executor.args.lock().unwrap(), [["version"]]);
+    }
+
+    #[derive(Debug, Default)]
+    struct HangingExecutor(ProcessExecutor);
+
+    impl Executor for HangingExecutor {
+        fn command(&self) -> Command {
+            let mut command = Command::new("sh");
+            command.args(["-c", "sleep 10"]);
+            command
+        }
+
+        fn output(&self, command: &mut Command, limits: &Limits) -> io::Result<Output> {
+            self.0.output(command, limits)
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        let started = Instant::now();
+        let result = super::with_executor(HangingExecutor::default(), || {
+            super::with_timeout(Duration::from_millis(100), nordvpn::status)
+        });
+
+        assert!(matches!(result, Err(CliError::TimedOut(_))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let cancel = CancelHandle::new();
+        let canceller = cancel.clone();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            canceller.cancel();
+        });
+
+        let started = Instant::now();
+        let result = super::with_executor(HangingExecutor::default(), || {
+            super::with_cancel(&cancel, || nordvpn::connect(None))
+        });
+
+        assert!(matches!(result, Err(CliError::Cancelled(_))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
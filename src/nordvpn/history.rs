@@ -0,0 +1,417 @@
+use super::cli::{self, CliResult, ConnectOption, Connected, ConnectionState, Status};
+use super::cli::{Protocol, Technology, Transfer};
+use byte_unit::Byte;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+pub const HISTORY_FILE: &str = "nordvpn-viking/history.jsonl";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisconnectReason {
+    Requested,
+    Dropped,
+    Switched,
+}
+
+// The transfer totals are in bytes, and are the last ones observed before the
+// session ended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub hostname: String,
+    pub country: String,
+    pub city: String,
+    pub technology: Technology,
+    pub protocol: Protocol,
+    pub received: u64,
+    pub sent: u64,
+    pub reason: Option<DisconnectReason>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryQuery {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub hostname: Option<String>,
+    pub country: Option<String>,
+}
+
+// Sessions are appended as JSON lines once when they start and again when they
+// end. The later line wins, so a session left open by a crash still shows up.
+#[derive(Debug)]
+pub struct ConnectionHistory {
+    path: PathBuf,
+    current: Option<Session>,
+    unsaved: bool,
+}
+
+impl Session {
+    fn start(status: &Status, at: DateTime<Utc>) -> Self {
+        Self {
+            started_at: status.uptime.started_at(at),
+            ended_at: None,
+            hostname: status.hostname.clone(),
+            country: status.country.clone(),
+            city: status.city.clone(),
+            technology: status.technology,
+            protocol: status.protocol,
+            received: status.transfer.received.get_bytes() as u64,
+            sent: status.transfer.sent.get_bytes() as u64,
+            reason: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.ended_at.is_none()
+    }
+
+    pub fn transfer(&self) -> Transfer {
+        Transfer {
+            received: Byte::from_bytes(self.received as u128),
+            sent: Byte::from_bytes(self.sent as u128),
+        }
+    }
+
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        self.started_at <= at && self.ended_at.is_none_or(|ended_at| at < ended_at)
+    }
+
+    fn is_same(&self, other: &Session) -> bool {
+        self.started_at == other.started_at && self.hostname == other.hostname
+    }
+}
+
+impl HistoryQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn since(mut self, since: DateTime<Utc>) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    pub fn until(mut self, until: DateTime<Utc>) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    pub fn hostname<S: Into<String>>(mut self, hostname: S) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    pub fn country<S: Into<String>>(mut self, country: S) -> Self {
+        self.country = Some(country.into());
+        self
+    }
+
+    // A session matches when any part of it falls between `since` and `until`.
+    pub fn matches(&self, session: &Session) -> bool {
+        self.since
+            .is_none_or(|since| session.ended_at.is_none_or(|ended_at| ended_at > since))
+            && self.until.is_none_or(|until| session.started_at < until)
+            && self
+                .hostname
+                .as_ref()
+                .is_none_or(|hostname| session.hostname.eq_ignore_ascii_case(hostname))
+            && self
+                .country
+                .as_ref()
+                .is_none_or(|country| session.country.eq_ignore_ascii_case(country))
+    }
+}
+
+impl ConnectionHistory {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut history = Self {
+            path,
+            current: None,
+            unsaved: false,
+        };
+        history.current = history.read()?.pop().filter(Session::is_open);
+
+        Ok(history)
+    }
+
+    pub fn open_default() -> io::Result<Self> {
+        match default_path() {
+            Some(path) => Self::open(path),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "neither XDG_DATA_HOME nor HOME is set",
+            )),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn current(&self) -> Option<&Session> {
+        self.current.as_ref()
+    }
+
+    pub fn connect(&mut self, option: Option<&ConnectOption>) -> CliResult<Connected> {
+        self.refresh()?;
+        let connected = cli::connect(option)?;
+        self.refresh()?;
+
+        Ok(connected)
+    }
+
+    // Polls once more beforehand, so the session ends with its final totals.
+    pub fn disconnect(&mut self) -> CliResult<bool> {
+        self.refresh()?;
+        let disconnected = cli::disconnect()?;
+        self.end(DisconnectReason::Requested, Utc::now())?;
+
+        Ok(disconnected)
+    }
+
+    pub fn refresh(&mut self) -> CliResult<()> {
+        let state = cli::status()?;
+        self.observe(&state, Utc::now())?;
+
+        Ok(())
+    }
+
+    // Meant to be called on a timer. Besides refreshing, this writes the totals
+    // of the open session, so that they survive the process if it never ends.
+    pub fn sample(&mut self) -> CliResult<()> {
+        self.refresh()?;
+
+        if let Some(current) = self.current.as_ref().filter(|_| self.unsaved) {
+            self.append(current)?;
+            self.unsaved = false;
+        }
+
+        Ok(())
+    }
+
+    // Feeds a polled state into the history. Transitional states are ignored,
+    // because the connection may still come back to the same server.
+    pub fn observe(&mut self, state: &ConnectionState, at: DateTime<Utc>) -> io::Result<()> {
+        match state {
+            ConnectionState::Connected(status) => match &mut self.current {
+                Some(current) if current.hostname == status.hostname => {
+                    current.technology = status.technology;
+                    current.protocol = status.protocol;
+                    current.received = status.transfer.received.get_bytes() as u64;
+                    current.sent = status.transfer.sent.get_bytes() as u64;
+                    self.unsaved = true;
+                }
+                _ => {
+                    self.end(DisconnectReason::Switched, at)?;
+
+                    let session = Session::start(status, at);
+                    self.append(&session)?;
+                    self.current = Some(session);
+                    self.unsaved = false;
+                }
+            },
+            ConnectionState::Disconnected => {
+                self.end(DisconnectReason::Dropped, at)?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn end(
+        &mut self,
+        reason: DisconnectReason,
+        at: DateTime<Utc>,
+    ) -> io::Result<Option<Session>> {
+        let mut session = match self.current.take() {
+            Some(session) => session,
+            None => return Ok(None),
+        };
+
+        session.ended_at = Some(at);
+        session.reason = Some(reason);
+        self.append(&session)?;
+
+        Ok(Some(session))
+    }
+
+    // The open session is reported with the latest totals, saved or not.
+    pub fn sessions(&self) -> io::Result<Vec<Session>> {
+        let mut sessions = self.read()?;
+
+        if let Some(current) = &self.current {
+            match sessions
+                .iter_mut()
+                .rev()
+                .find(|other| other.is_same(current))
+            {
+                Some(other) => *other = current.clone(),
+                None => sessions.push(current.clone()),
+            }
+        }
+
+        Ok(sessions)
+    }
+
+    // Rewrites the file with only the latest line of every session.
+    pub fn compact(&self) -> io::Result<()> {
+        let mut text = String::new();
+
+        for session in self.read()? {
+            text.push_str(&serde_json::to_string(&session)?);
+            text.push('\n');
+        }
+
+        let temp_path = self.path.with_extension("jsonl.tmp");
+        fs::write(&temp_path, text)?;
+        fs::rename(temp_path, &self.path)
+    }
+
+    fn read(&self) -> io::Result<Vec<Session>> {
+        let reader = match File::open(&self.path) {
+            Ok(file) => BufReader::new(file),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error),
+        };
+        let mut sessions: Vec<Session> = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let session: Session = serde_json::from_str(&line)?;
+
+            match sessions
+                .iter_mut()
+                .rev()
+                .find(|other| other.is_same(&session))
+            {
+                Some(other) => *other = session,
+                None => sessions.push(session),
+            }
+        }
+
+        Ok(sessions)
+    }
+
+    pub fn query(&self, query: &HistoryQuery) -> io::Result<Vec<Session>> {
+        let mut sessions = self.sessions()?;
+        sessions.retain(|session| query.matches(session));
+
+        Ok(sessions)
+    }
+
+    pub fn session_at(&self, at: DateTime<Utc>) -> io::Result<Option<Session>> {
+        Ok(self
+            .sessions()?
+            .into_iter()
+            .rev()
+            .find(|session| session.contains(at)))
+    }
+
+    fn append(&self, session: &Session) -> io::Result<()> {
+        let mut line = serde_json::to_string(session)?;
+        line.push('\n');
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    let data_dir = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+
+    Some(data_dir.join(HISTORY_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionHistory, DisconnectReason, HistoryQuery};
+    use crate::nordvpn::executor::with_executor;
+    use crate::nordvpn::simulator::Simulator;
+    use crate::nordvpn::tests::TempDir;
+    use crate::nordvpn::{self, ConnectOption, ConnectionState};
+    use chrono::{Duration, Utc};
+    use std::fs;
+
+    #[test]
+    fn test_connection_history() {
+        let temp_dir = TempDir::new("test_connection_history");
+
+        let path = temp_dir.path().join("history.jsonl");
+        let mut history = ConnectionHistory::open(&path).unwrap();
+
+        with_executor(Simulator::new(temp_dir.path().join("state")), || {
+            history.connect(None).unwrap();
+            assert!(history.current().is_some());
+
+            let germany = ConnectOption::CountryCode("de".to_owned());
+            history.connect(Some(&germany)).unwrap();
+
+            // A session left open is picked up again by the next instance.
+            history = ConnectionHistory::open(&path).unwrap();
+            assert_eq!(history.current().unwrap().country, "Germany");
+
+            history.disconnect().unwrap();
+            assert!(history.current().is_none());
+
+            nordvpn::connect(None).unwrap();
+            history.refresh().unwrap();
+            history.sample().unwrap();
+        });
+
+        let lines = fs::read_to_string(&path).unwrap().lines().count();
+        history.compact().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        assert!(lines > 3);
+
+        let sessions = history.sessions().unwrap();
+        assert_eq!(sessions.len(), 3);
+        assert_eq!(sessions[0].reason, Some(DisconnectReason::Switched));
+        assert_eq!(sessions[1].reason, Some(DisconnectReason::Requested));
+        assert!(sessions[2].is_open());
+
+        history
+            .observe(&ConnectionState::Disconnected, Utc::now())
+            .unwrap();
+        let sessions = history.sessions().unwrap();
+        assert_eq!(sessions[2].reason, Some(DisconnectReason::Dropped));
+
+        let germany = history
+            .query(&HistoryQuery::new().country("germany"))
+            .unwrap();
+        assert_eq!(germany, [sessions[1].clone()]);
+
+        let (started_at, ended_at) = (sessions[1].started_at, sessions[1].ended_at.unwrap());
+        let during = started_at + (ended_at - started_at) / 2;
+        assert_eq!(
+            history.session_at(during).unwrap(),
+            Some(sessions[1].clone())
+        );
+
+        let future = HistoryQuery::new().since(Utc::now() + Duration::hours(1));
+        assert!(history.query(&future).unwrap().is_empty());
+    }
+}
@@ -1,15 +1,107 @@
 mod cli;
+mod country;
+mod credentials;
+mod executor;
+mod history;
+pub mod nonblocking;
+mod pool;
 mod re;
+pub mod simulator;
+mod subprocess;
+mod throughput;
+mod tokenizer;
+mod transcript;
+mod uptime;
+mod usage;
+mod watcher;
 
 pub use cli::*;
-pub use re::RegexError;
+pub use country::{Country, FlagStyle, COUNTRIES, FLAGS_PREFIX};
+pub use credentials::{CredentialStore, MemoryStore, SecretServiceStore};
+pub use executor::{
+    executor, limits, set_executor, set_timeout, timeout, with_cancel, with_limits, with_timeout,
+    CancelHandle, Executor, Limits, OutputFuture, ProcessExecutor,
+};
+pub use history::{ConnectionHistory, DisconnectReason, HistoryQuery, Session};
+pub use re::{ParseError, Profile, RegexError, PROFILES};
+pub use throughput::{Rate, Throughput, ThroughputSampler};
+pub use tokenizer::{Record, Records};
+pub use transcript::{
+    read_transcript, record_transcript, RecordingExecutor, ReplayExecutor, TranscriptEntry,
+    REDACTED,
+};
+pub use uptime::Uptime;
+pub use usage::{to_csv, to_json, usage, CapWarning, DataCap, Grouping, UsageRow};
+pub use watcher::{StatusEvent, StatusWatcher};
 
 #[cfg(test)]
 mod tests {
+    use super::executor::with_executor;
+    use super::simulator::{Simulator, SimulatorState};
+    use super::TranscriptEntry;
     use semver::Version;
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // A fresh directory under the system temp dir, removed again when the
+    // guard is dropped. Tests across the module share it for their state.
+    pub(super) struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub(super) fn new(name: &str) -> Self {
+            let path =
+                env::temp_dir().join(format!("nordvpn-viking-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+
+            Self(path)
+        }
+
+        pub(super) fn path(&self) -> &Path {
+            &self.0
+        }
+
+        pub(super) fn simulator(&self) -> Simulator {
+            Simulator::new(&self.0)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // A successful command for `ReplayExecutor` to answer `args` with.
+    fn entry(args: &[&str], stdout: &str) -> TranscriptEntry {
+        TranscriptEntry {
+            argv: ["nordvpn"]
+                .iter()
+                .chain(args)
+                .map(|arg| arg.to_string())
+                .collect(),
+            stdout: stdout.to_owned(),
+            stderr: String::new(),
+            status: Some(0),
+            started_at: "2022-01-14T18:30:00+00:00".to_owned(),
+            elapsed_ms: 20,
+        }
+    }
 
     #[test]
     fn test_nordvpn() {
+        let temp_dir = TempDir::new("test_nordvpn");
+        with_executor(temp_dir.simulator(), run_nordvpn);
+    }
+
+    #[test]
+    fn test_settings() {
+        let temp_dir = TempDir::new("test_settings");
+        with_executor(temp_dir.simulator(), run_settings);
+    }
+
+    fn run_nordvpn() {
         let version = super::version().unwrap();
         println!("Version: {}", version);
         assert!(version >= Version::new(3, 12, 0));
@@ -45,7 +137,508 @@ mod tests {
     }
 
     #[test]
-    fn test_settings() {
+    fn test_whitelist() {
+        let temp_dir = TempDir::new("test_whitelist");
+        with_executor(temp_dir.simulator(), run_whitelist);
+    }
+
+    fn run_whitelist() {
+        let mut whitelist = super::Whitelist::default();
+
+        assert!(whitelist.add_port(22, Some(super::Protocol::Tcp)).unwrap());
+        assert!(!whitelist.add_port(22, Some(super::Protocol::Tcp)).unwrap());
+        assert!(whitelist.add_port_range(3000, 5000, None).unwrap());
+        assert!(whitelist
+            .add_subnet("192.168.0.0/16".parse().unwrap())
+            .unwrap());
+        println!("Whitelist: {:#?}", whitelist);
+
+        assert!(whitelist.remove_port_range(3000, 5000, None).unwrap());
+        assert!(!whitelist.remove_port(23, None).unwrap());
+        assert!(matches!(
+            whitelist.add_port_range(5000, 3000, None),
+            Err(super::CliError::InvalidWhitelistEntry(_))
+        ));
+        assert!(matches!(
+            super::whitelist(["add", "subnet", "192.168.0.0/33"]),
+            Err(super::CliError::InvalidWhitelistEntry(_))
+        ));
+        assert_eq!(whitelist.ports.len(), 1);
+
+        let settings = super::settings().unwrap();
+        assert_eq!(settings.whitelist, whitelist);
+
+        whitelist.remove_all().unwrap();
+        assert_eq!(whitelist, super::Whitelist::default());
+
+        let mut settings = settings;
+        settings
+            .whitelist
+            .add_subnet("10.0.0.0/8".parse().unwrap())
+            .unwrap();
+        settings.whitelist.ports.insert(super::WhitelistedPorts {
+            start: 8000,
+            end: 8080,
+            protocol: Some(super::Protocol::Udp),
+        });
+        settings.update().unwrap();
+        assert_eq!(super::settings().unwrap(), settings);
+    }
+
+    #[test]
+    fn test_rate() {
+        let temp_dir = TempDir::new("test_rate");
+        with_executor(temp_dir.simulator(), run_rate);
+    }
+
+    fn run_rate() {
+        use super::{ConnectionRating, RateOutcome};
+
+        assert_eq!(ConnectionRating::new(0), None);
+        assert_eq!(ConnectionRating::try_from(6).unwrap_err().0, 6);
+
+        let rating = ConnectionRating::new(4).unwrap();
+        assert_eq!(super::rate(rating).unwrap(), RateOutcome::NoConnection);
+
+        super::connect(None).unwrap();
+        super::disconnect().unwrap();
+
+        assert_eq!(super::rate(rating).unwrap(), RateOutcome::Rated);
+        assert_eq!(super::rate(rating).unwrap(), RateOutcome::AlreadyRated);
+    }
+
+    #[test]
+    fn test_register() {
+        let temp_dir = TempDir::new("test_register");
+        with_executor(temp_dir.simulator(), run_register);
+    }
+
+    fn run_register() {
+        use super::Registration;
+
+        assert_eq!(super::register().unwrap(), Registration::AlreadyLoggedIn);
+        assert!(super::logout().unwrap());
+
+        let registration = super::register().unwrap();
+        println!("Registration: {:?}", registration);
+        assert!(matches!(registration, Registration::Url(url) if url.starts_with("https://")));
+    }
+
+    #[test]
+    fn test_register_prompt() {
+        let transcript = TranscriptEntry {
+            stderr: "EOF\n".to_owned(),
+            status: Some(1),
+            ..entry(&["register"], "Email: \n")
+        };
+        let registration = with_executor(super::ReplayExecutor::new([transcript]), || {
+            super::register().unwrap()
+        });
+
+        assert_eq!(registration, super::Registration::CredentialsRequired);
+    }
+
+    #[test]
+    fn test_register_with() {
+        let temp_dir = TempDir::new("test_register_with");
+        let simulator = temp_dir.simulator();
+        let mut state = simulator.state().unwrap();
+
+        state.email = None;
+        state.register_prompts = true;
+        simulator.set_state(&state).unwrap();
+
+        with_executor(simulator, run_register_with);
+    }
+
+    fn run_register_with() {
+        use super::Registration;
+
+        assert_eq!(
+            super::register().unwrap(),
+            Registration::CredentialsRequired
+        );
+        assert_eq!(
+            super::register_with("new@example.com", "short").unwrap(),
+            Registration::Failed("Password must be at least 8 characters long.".to_owned())
+        );
+        assert_eq!(
+            super::register_with("new@example.com", "correct horse").unwrap(),
+            Registration::Registered
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        use super::{CliError, RegexError, ReplayExecutor};
+
+        let status = "Status: Connected\nCurrent server: se497.nordvpn.com\nCountry: Sweden\nCity: Stockholm\nServer IP: 198.51.100.999\nCurrent technology: NORDLYNX\nCurrent protocol: UDP\nTransfer: 1.2 MiB received, 300 KiB sent\nUptime: 5 seconds\n";
+        let replay = ReplayExecutor::new([
+            entry(&["version"], "NordVPN Version 3.12.3\n"),
+            entry(&["status"], status),
+            entry(
+                &["status"],
+                &status
+                    .replace("City: Stockholm", "Town: Stockholm")
+                    .replace("100.999", "100.1"),
+            ),
+        ]);
+
+        with_executor(replay, || {
+            super::version().unwrap();
+
+            match super::status() {
+                Err(CliError::ParseError(error, _)) => {
+                    assert_eq!(error.field, RegexError::StatusIp);
+                    assert_eq!(error.value, "198.51.100.999");
+                    assert_eq!((error.line, error.column), (5, 12));
+                    assert_eq!(error.output, status);
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+
+            match super::status() {
+                Err(CliError::RegexError(error, _)) => {
+                    assert_eq!(error.field, RegexError::StatusCity);
+                    assert_eq!(error.value, "Town: Stockholm");
+                    assert_eq!(error.line, 4);
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn test_normalize_output() {
+        use super::{Notice, ReplayExecutor};
+
+        let replay = ReplayExecutor::new([
+            entry(
+                &["countries"],
+                "\r-\r  \r|\r\x1b[1;33mA new version of NordVPN is available! Please update the application.\x1b[0m\nGermany, Sweden, \x1b[32mUnited_States\x1b[0m\n",
+            ),
+            entry(
+                &["connect", "Sweden"],
+                "\r-\r  \r\\\r  \rConnecting to Sweden #497 (se497.nordvpn.com)\r\n\r-\r  \rYou are connected to Sweden #497 (se497.nordvpn.com)!\r\n",
+            ),
+        ]);
+
+        with_executor(replay, || {
+            let (countries, notices) = super::with_notices(|| super::countries().unwrap());
+            assert_eq!(countries, ["Germany", "Sweden", "United_States"]);
+            assert_eq!(
+                notices,
+                [Notice::UpdateAvailable(
+                    "A new version of NordVPN is available! Please update the application."
+                        .to_owned()
+                )]
+            );
+
+            let (connected, notices) = super::with_notices(|| {
+                super::connect(Some(&super::ConnectOption::Country("Sweden".to_owned())))
+            });
+            assert_eq!(connected.unwrap().hostname, "se497.nordvpn.com");
+            assert!(notices.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_diagnose() {
+        use super::{CliError, Diagnosis, ProcessExecutor, ReplayExecutor};
+
+        let temp_dir = TempDir::new("test_diagnose");
+
+        let simulator = temp_dir.simulator();
+        let mut state = simulator.state().unwrap();
+        let diagnose = |simulator: &Simulator, state: &SimulatorState| {
+            simulator.set_state(state).unwrap();
+            with_executor(simulator.clone(), super::diagnose).unwrap()
+        };
+
+        assert_eq!(diagnose(&simulator, &state), Diagnosis::Ready);
+
+        state.daemon_running = false;
+        assert_eq!(diagnose(&simulator, &state), Diagnosis::DaemonUnreachable);
+        assert!(matches!(
+            with_executor(simulator.clone(), super::settings),
+            Err(CliError::DaemonUnreachable(_))
+        ));
+
+        state.socket_accessible = false;
+        assert_eq!(diagnose(&simulator, &state), Diagnosis::PermissionDenied);
+
+        let missing = ProcessExecutor::new("nordvpn-viking-missing-binary");
+        assert_eq!(
+            with_executor(missing, super::diagnose).unwrap(),
+            Diagnosis::NotInstalled
+        );
+
+        // Only a binary that fails to spawn counts as missing.
+        let replay = ReplayExecutor::new(Vec::new());
+        assert!(matches!(
+            with_executor(replay, super::version),
+            Err(CliError::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_known_countries() {
+        use super::{ConnectOption, Country, FlagStyle};
+
+        let temp_dir = TempDir::new("test_known_countries");
+
+        with_executor(temp_dir.simulator(), || {
+            let countries = super::known_countries().unwrap();
+            assert_eq!(countries.len(), super::countries().unwrap().len());
+
+            let uk = Country::from_name("United Kingdom").unwrap();
+            assert!(countries.contains(&uk));
+            assert_eq!(uk.flag_path(FlagStyle::Square), "flags/square/gb.svg");
+
+            let option = ConnectOption::from(uk);
+            assert_eq!(option.country(), Some(uk));
+
+            let connected = super::connect(Some(&option)).unwrap();
+            assert_eq!(Country::from_name(&connected.country), Some(uk));
+        });
+    }
+
+    #[test]
+    fn test_connection_state() {
+        use super::ConnectionState;
+
+        let temp_dir = TempDir::new("test_connection_state");
+
+        let simulator = temp_dir.simulator();
+        let status = || with_executor(simulator.clone(), super::status).unwrap();
+
+        assert_eq!(status(), ConnectionState::Disconnected);
+
+        with_executor(simulator.clone(), || super::connect(None)).unwrap();
+        assert!(status().is_connected());
+
+        // One day, two hours, three minutes and four seconds ago.
+        let mut state = simulator.state().unwrap();
+        state.connected_at -= 93_784;
+        simulator.set_state(&state).unwrap();
+
+        let uptime = status().into_status().unwrap().uptime;
+        assert!((93_784..93_790).contains(&uptime.as_secs()));
+        assert_eq!((uptime.days, uptime.hours, uptime.minutes), (1, 2, 3));
+
+        for phase in ["Connecting", "Reconnecting", "Paused"] {
+            let mut state = simulator.state().unwrap();
+            state.phase = Some(phase.to_owned());
+            simulator.set_state(&state).unwrap();
+
+            match (phase, status()) {
+                ("Connecting", ConnectionState::Connecting(partial))
+                | ("Reconnecting", ConnectionState::Reconnecting(partial)) => {
+                    assert_eq!(partial.hostname.as_deref(), Some("us8412.nordvpn.com"));
+                    assert_eq!(partial.ip, None);
+                    assert_eq!(partial.transfer, None);
+                }
+                ("Paused", ConnectionState::Unknown(raw)) => {
+                    assert!(raw.starts_with("Status: Paused"))
+                }
+                (phase, state) => panic!("unexpected state {:?} for {}", state, phase),
+            }
+        }
+    }
+
+    #[test]
+    fn test_login_flow() {
+        let temp_dir = TempDir::new("test_login_flow");
+        with_executor(temp_dir.simulator(), run_login_flow);
+    }
+
+    fn run_login_flow() {
+        use super::{CliError, LoginFlow};
+
+        assert!(super::logout().unwrap());
+
+        let mut flow = LoginFlow::new();
+        assert!(matches!(
+            flow.complete("nordvpn://login?action=login&exchange_token=ZXhhbXBsZQ&status=done"),
+            Err(CliError::LoginNotAwaitingCallback)
+        ));
+
+        flow.begin().unwrap();
+        println!("Login URL: {:?}", flow.url());
+        assert!(flow.url().is_some());
+
+        assert!(matches!(
+            flow.complete("https://example.com/"),
+            Err(CliError::InvalidCallbackUrl(_))
+        ));
+        assert!(matches!(
+            flow.complete("nordvpn://login?action=login&status=done"),
+            Err(CliError::InvalidCallbackUrl(_))
+        ));
+
+        let account = flow
+            .complete("nordvpn://login?action=login&exchange_token=ZXhhbXBsZQ&status=done")
+            .unwrap()
+            .clone();
+        assert_eq!(flow.account(), Some(&account));
+        assert_eq!(super::account().unwrap(), Some(account));
+
+        assert!(matches!(
+            flow.complete("nordvpn://login?action=login&exchange_token=ZXhhbXBsZQ&status=done"),
+            Err(CliError::LoginNotAwaitingCallback)
+        ));
+    }
+
+    #[test]
+    fn test_login_with_token() {
+        let temp_dir = TempDir::new("test_login_with_token");
+        with_executor(temp_dir.simulator(), run_login_with_token);
+    }
+
+    fn run_login_with_token() {
+        use super::{CliError, CredentialStore, MemoryStore};
+
+        assert!(super::logout().unwrap());
+        assert!(matches!(
+            super::login_with_token("not a token"),
+            Err(CliError::InvalidToken)
+        ));
+
+        let store = MemoryStore::new();
+        assert_eq!(super::login_with_stored_token(&store).unwrap(), None);
+
+        store.store_token("e9f2ab6b1c7d4e8f").unwrap();
+        let account = super::login_with_stored_token(&store).unwrap().unwrap();
+        assert_eq!(super::account().unwrap(), Some(account));
+    }
+
+    #[test]
+    fn test_login_with_token_redacted() {
+        use super::{CliError, ReplayExecutor, REDACTED};
+
+        let transcript = TranscriptEntry {
+            status: Some(1),
+            ..entry(
+                &["login", "--token", REDACTED],
+                "Whoops! Something went wrong.\n",
+            )
+        };
+        let error = with_executor(ReplayExecutor::new([transcript]), || {
+            super::login_with_token("e9f2ab6b1c7d4e8f").unwrap_err()
+        });
+
+        assert!(matches!(error, CliError::FailedCommand(_)));
+        assert!(!format!("{:?}", error).contains("e9f2ab6b1c7d4e8f"));
+        assert!(format!("{:?}", error).contains(REDACTED));
+    }
+
+    #[test]
+    fn test_connect_to() {
+        let temp_dir = TempDir::new("test_connect_to");
+        with_executor(temp_dir.simulator(), run_connect_to);
+    }
+
+    fn run_connect_to() {
+        use super::{CliError, ConnectTarget};
+
+        let connected =
+            super::connect_to(&ConnectTarget::new().group("P2P").country("Germany")).unwrap();
+        assert_eq!(connected.country, "Germany");
+
+        let connected =
+            super::connect_to(&ConnectTarget::new().group("p2p").country_code("us")).unwrap();
+        assert_eq!(connected.country, "United States");
+
+        for target in [
+            ConnectTarget::new().group("P2P").city("Frankfurt"),
+            ConnectTarget::new().group("P2P").server("de1021"),
+        ] {
+            assert!(matches!(
+                super::connect_to(&target),
+                Err(CliError::InvalidConnectTarget(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_autoconnect() {
+        let temp_dir = TempDir::new("test_autoconnect");
+        with_executor(temp_dir.simulator(), run_autoconnect);
+    }
+
+    fn run_autoconnect() {
+        use super::{AutoconnectTarget, ConnectOption};
+
+        let mut settings = super::settings().unwrap();
+
+        for target in [
+            ConnectOption::CountryCity("Sweden".to_owned(), "Stockholm".to_owned()),
+            ConnectOption::City("Stockholm".to_owned()),
+            ConnectOption::Group("P2P".to_owned()),
+        ] {
+            settings.set_autoconnect_to(target.clone()).unwrap();
+            assert_eq!(
+                super::settings().unwrap().autoconnect_target,
+                Some(AutoconnectTarget::from(&target))
+            );
+            assert_eq!(super::settings().unwrap(), settings);
+        }
+
+        settings
+            .set_autoconnect_to(ConnectOption::Server("se497".to_owned()))
+            .unwrap();
+        assert_eq!(super::settings().unwrap(), settings);
+
+        settings.set_autoconnect(true).unwrap();
+        assert_eq!(super::settings().unwrap().autoconnect_target, None);
+        assert_eq!(super::settings().unwrap(), settings);
+    }
+
+    #[test]
+    fn test_profiles() {
+        for version in ["3.12.3", "3.16.1"] {
+            let temp_dir = TempDir::new(&format!("test_profiles-{}", version));
+            let simulator = temp_dir.simulator();
+            let mut state = simulator.state().unwrap();
+            state.version = version.to_owned();
+            state.whitelist_ports.push((22, 22, None));
+            simulator.set_state(&state).unwrap();
+
+            with_executor(simulator, || run_profile(version));
+        }
+    }
+
+    fn run_profile(version: &str) {
+        // Output is parsed before the version is known by trying each profile.
+        assert!(super::profile().is_none());
+
+        super::connect(None).unwrap();
+        let status = super::status().unwrap().into_status().unwrap();
+        assert_eq!(status.hostname, "us8412.nordvpn.com");
+
+        let settings = super::settings().unwrap();
+        assert_eq!(settings.whitelist.ports.len(), 1);
+        assert!(settings.firewall);
+        assert!(!settings.ipv6);
+
+        assert_eq!(super::version().unwrap().to_string(), version);
+        let profile = super::profile().unwrap();
+        assert!(profile.versions.matches(&version.parse().unwrap()));
+        assert_eq!(super::settings().unwrap(), settings);
+
+        if profile.name == "3.16" {
+            let extra = settings
+                .extra
+                .keys()
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            assert_eq!(extra, ["Firewall Mark", "Meshnet", "Routing"]);
+        } else {
+            assert!(settings.extra.is_empty());
+        }
+    }
+
+    fn run_settings() {
         let settings = super::settings().unwrap();
         println!("Settings: {:#?}", settings);
     }
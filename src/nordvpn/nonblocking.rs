@@ -0,0 +1,512 @@
+use super::cli::Whitelist;
+use super::cli::{
+    self, CliResult, ConnectOption, ConnectTarget, LoginFlow, Protocol, Settings, Technology,
+};
+use super::cli::{
+    Account, Connected, ConnectionRating, ConnectionState, Diagnosis, Notice, RateOutcome,
+    Registration,
+};
+use super::country::Country;
+use super::credentials::CredentialStore;
+use super::executor::Runner;
+use super::pool;
+use ipnet::IpNet;
+use semver::Version;
+use std::future::{self, Future};
+use std::net::IpAddr;
+use std::pin::{pin, Pin};
+
+pub type CliFuture<T> = Pin<Box<dyn Future<Output = CliResult<T>> + Send>>;
+
+// The executor and limits are taken from the calling thread right away, the
+// commands only run once the future is polled.
+fn call<T, F, R>(run: F) -> CliFuture<T>
+where
+    F: FnOnce(Runner) -> R,
+    R: Future<Output = CliResult<T>> + Send + 'static,
+{
+    Box::pin(run(Runner::nonblocking()))
+}
+
+// Collects the notices printed by the commands of `future`, on whichever
+// thread it is polled. As with `nordvpn::with_notices`, no other caller's are
+// included.
+pub async fn with_notices<F: Future>(future: F) -> (F::Output, Vec<Notice>) {
+    let mut future = pin!(future);
+    let mut notices = Vec::new();
+    let output = future::poll_fn(|context| {
+        cli::collect_notices(&mut notices, || future.as_mut().poll(context))
+    })
+    .await;
+
+    (output, notices)
+}
+
+pub fn account() -> CliFuture<Option<Account>> {
+    call(|runner| async move { cli::account_on(&runner).await })
+}
+
+pub fn cities(country: String) -> CliFuture<Vec<String>> {
+    call(|runner| async move { cli::cities_on(&runner, &country).await })
+}
+
+pub fn connect(option: Option<ConnectOption>) -> CliFuture<Connected> {
+    call(|runner| async move { cli::connect_on(&runner, option.as_ref()).await })
+}
+
+pub fn connect_to(target: ConnectTarget) -> CliFuture<Connected> {
+    call(|runner| async move { cli::connect_to_on(&runner, &target).await })
+}
+
+pub fn countries() -> CliFuture<Vec<String>> {
+    call(|runner| async move { cli::countries_on(&runner).await })
+}
+
+pub fn diagnose() -> CliFuture<Diagnosis> {
+    call(|runner| async move { cli::diagnose_on(&runner).await })
+}
+
+pub fn disconnect() -> CliFuture<bool> {
+    call(|runner| async move { cli::disconnect_on(&runner).await })
+}
+
+pub fn groups() -> CliFuture<Vec<String>> {
+    call(|runner| async move { cli::groups_on(&runner).await })
+}
+
+pub fn known_countries() -> CliFuture<Vec<&'static Country>> {
+    call(|runner| async move { cli::known_countries_on(&runner).await })
+}
+
+pub fn login() -> CliFuture<Option<String>> {
+    call(|runner| async move { cli::login_on(&runner).await })
+}
+
+pub fn login_callback(url: String) -> CliFuture<Account> {
+    call(|runner| async move { cli::login_callback_on(&runner, &url).await })
+}
+
+pub fn login_with_token(token: String) -> CliFuture<Account> {
+    call(|runner| async move { cli::login_with_token_on(&runner, &token).await })
+}
+
+// Credential stores block, so the token is loaded on a worker thread.
+pub fn login_with_stored_token<S>(store: S) -> CliFuture<Option<Account>>
+where
+    S: CredentialStore + 'static,
+{
+    call(|runner| async move {
+        match pool::spawn(move || store.load_token()).await? {
+            Some(token) => cli::login_with_token_on(&runner, &token).await.map(Some),
+            None => Ok(None),
+        }
+    })
+}
+
+pub fn logout() -> CliFuture<bool> {
+    call(|runner| async move { cli::logout_on(&runner).await })
+}
+
+pub fn rate(rating: ConnectionRating) -> CliFuture<RateOutcome> {
+    call(|runner| async move { cli::rate_on(&runner, rating).await })
+}
+
+pub fn register() -> CliFuture<Registration> {
+    call(|runner| async move { cli::register_on(&runner).await })
+}
+
+pub fn register_with(email: String, password: String) -> CliFuture<Registration> {
+    call(|runner| async move { cli::register_with_on(&runner, &email, &password).await })
+}
+
+pub fn settings() -> CliFuture<Settings> {
+    call(|runner| async move { cli::settings_on(&runner).await })
+}
+
+pub fn status() -> CliFuture<ConnectionState> {
+    call(|runner| async move { cli::status_on(&runner).await })
+}
+
+pub fn whitelist(args: Vec<String>) -> CliFuture<bool> {
+    call(|runner| async move { cli::whitelist_on(&runner, args).await })
+}
+
+pub fn version() -> CliFuture<Version> {
+    call(|runner| async move { cli::version_on(&runner).await })
+}
+
+impl Settings {
+    pub fn update_async(&mut self) -> impl Future<Output = CliResult<()>> + '_ {
+        let runner = Runner::nonblocking();
+
+        async move { self.update_on(&runner).await }
+    }
+
+    pub fn set_technology_async(
+        &mut self,
+        technology: Technology,
+    ) -> impl Future<Output = CliResult<&mut Self>> {
+        let runner = Runner::nonblocking();
+
+        async move { self.set_technology_on(&runner, technology).await }
+    }
+
+    pub fn set_protocol_async(
+        &mut self,
+        protocol: Protocol,
+    ) -> impl Future<Output = CliResult<&mut Self>> {
+        let runner = Runner::nonblocking();
+
+        async move { self.set_protocol_on(&runner, protocol).await }
+    }
+
+    pub fn set_firewall_async(
+        &mut self,
+        enabled: bool,
+    ) -> impl Future<Output = CliResult<&mut Self>> {
+        let runner = Runner::nonblocking();
+
+        async move { self.set_firewall_on(&runner, enabled).await }
+    }
+
+    pub fn set_killswitch_async(
+        &mut self,
+        enabled: bool,
+    ) -> impl Future<Output = CliResult<&mut Self>> {
+        let runner = Runner::nonblocking();
+
+        async move { self.set_killswitch_on(&runner, enabled).await }
+    }
+
+    pub fn set_cybersec_async(
+        &mut self,
+        enabled: bool,
+    ) -> impl Future<Output = CliResult<&mut Self>> {
+        let runner = Runner::nonblocking();
+
+        async move { self.set_cybersec_on(&runner, enabled).await }
+    }
+
+    pub fn set_obfuscate_async(
+        &mut self,
+        enabled: bool,
+    ) -> impl Future<Output = CliResult<&mut Self>> {
+        let runner = Runner::nonblocking();
+
+        async move { self.set_obfuscate_on(&runner, enabled).await }
+    }
+
+    pub fn set_notify_async(
+        &mut self,
+        enabled: bool,
+    ) -> impl Future<Output = CliResult<&mut Self>> {
+        let runner = Runner::nonblocking();
+
+        async move { self.set_notify_on(&runner, enabled).await }
+    }
+
+    pub fn set_autoconnect_async(
+        &mut self,
+        enabled: bool,
+    ) -> impl Future<Output = CliResult<&mut Self>> {
+        let runner = Runner::nonblocking();
+
+        async move { self.set_autoconnect_on(&runner, enabled).await }
+    }
+
+    pub fn set_autoconnect_to_async(
+        &mut self,
+        target: ConnectOption,
+    ) -> impl Future<Output = CliResult<&mut Self>> {
+        let runner = Runner::nonblocking();
+
+        async move { self.set_autoconnect_to_on(&runner, target).await }
+    }
+
+    pub fn set_ipv6_async(&mut self, enabled: bool) -> impl Future<Output = CliResult<&mut Self>> {
+        let runner = Runner::nonblocking();
+
+        async move { self.set_ipv6_on(&runner, enabled).await }
+    }
+
+    pub fn set_dns_async<V>(
+        &mut self,
+        addresses: Option<V>,
+    ) -> impl Future<Output = CliResult<&mut Self>>
+    where
+        V: IntoIterator<Item = IpAddr> + Send + 'static,
+    {
+        let runner = Runner::nonblocking();
+
+        async move { self.set_dns_on(&runner, addresses).await }
+    }
+}
+
+impl LoginFlow {
+    pub fn begin_async(&mut self) -> impl Future<Output = CliResult<&Self>> {
+        let runner = Runner::nonblocking();
+
+        async move { self.begin_on(&runner).await }
+    }
+
+    pub fn complete_async(
+        &mut self,
+        callback: String,
+    ) -> impl Future<Output = CliResult<&Account>> {
+        let runner = Runner::nonblocking();
+
+        async move { self.complete_on(&runner, &callback).await }
+    }
+}
+
+impl Whitelist {
+    pub fn update_async(&mut self) -> impl Future<Output = CliResult<()>> + '_ {
+        let runner = Runner::nonblocking();
+
+        async move { self.update_on(&runner).await }
+    }
+
+    pub fn add_port_async(
+        &mut self,
+        port: u16,
+        protocol: Option<Protocol>,
+    ) -> impl Future<Output = CliResult<bool>> + '_ {
+        let runner = Runner::nonblocking();
+
+        async move { self.add_port_on(&runner, port, protocol).await }
+    }
+
+    pub fn remove_port_async(
+        &mut self,
+        port: u16,
+        protocol: Option<Protocol>,
+    ) -> impl Future<Output = CliResult<bool>> + '_ {
+        let runner = Runner::nonblocking();
+
+        async move { self.remove_port_on(&runner, port, protocol).await }
+    }
+
+    pub fn add_port_range_async(
+        &mut self,
+        start: u16,
+        end: u16,
+        protocol: Option<Protocol>,
+    ) -> impl Future<Output = CliResult<bool>> + '_ {
+        let runner = Runner::nonblocking();
+
+        async move { self.add_port_range_on(&runner, start, end, protocol).await }
+    }
+
+    pub fn remove_port_range_async(
+        &mut self,
+        start: u16,
+        end: u16,
+        protocol: Option<Protocol>,
+    ) -> impl Future<Output = CliResult<bool>> + '_ {
+        let runner = Runner::nonblocking();
+
+        async move {
+            self.remove_port_range_on(&runner, start, end, protocol)
+                .await
+        }
+    }
+
+    pub fn add_subnet_async(
+        &mut self,
+        subnet: IpNet,
+    ) -> impl Future<Output = CliResult<bool>> + '_ {
+        let runner = Runner::nonblocking();
+
+        async move { self.add_subnet_on(&runner, subnet).await }
+    }
+
+    pub fn remove_subnet_async(
+        &mut self,
+        subnet: IpNet,
+    ) -> impl Future<Output = CliResult<bool>> + '_ {
+        let runner = Runner::nonblocking();
+
+        async move { self.remove_subnet_on(&runner, subnet).await }
+    }
+
+    pub fn remove_all_async(&mut self) -> impl Future<Output = CliResult<()>> + '_ {
+        let runner = Runner::nonblocking();
+
+        async move { self.remove_all_on(&runner).await }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nordvpn::executor::{block_on, with_executor};
+    use crate::nordvpn::tests::TempDir;
+    use crate::nordvpn::{self, nonblocking, CancelHandle, CliError, Diagnosis, Notice};
+    use crate::nordvpn::{Executor, Limits, OutputFuture, ProcessExecutor};
+    use semver::Version;
+    use std::future::Future;
+    use std::io;
+    use std::process::{Command, Output};
+    use std::sync::Arc;
+    use std::task::{Context, Waker};
+    use std::time::{Duration, Instant};
+
+    // Runs a shell script in place of the daemon, as a real process.
+    #[derive(Debug)]
+    struct ScriptExecutor(&'static str);
+
+    impl Executor for ScriptExecutor {
+        fn command(&self) -> Command {
+            let mut command = Command::new("sh");
+            command.args(["-c", self.0, "nordvpn"]);
+            command
+        }
+
+        fn output(&self, command: &mut Command, limits: &Limits) -> io::Result<Output> {
+            ProcessExecutor::default().output(command, limits)
+        }
+
+        fn output_async(
+            self: Arc<Self>,
+            command: &Command,
+            input: Option<Vec<u8>>,
+            limits: Limits,
+        ) -> OutputFuture {
+            Arc::new(ProcessExecutor::default()).output_async(command, input, limits)
+        }
+    }
+
+    const VERSION: &str = "echo 'NordVPN Version 3.12.3'";
+    const HANGING: &str = "sleep 10";
+    const OUTDATED: &str = "echo 'A new version of NordVPN is available! Please update the application.'; echo 'Germany, Sweden'";
+
+    // The version is reported, a hanging command times out, and a missing
+    // binary is told apart, however the future is driven.
+    fn check_processes<B>(block_on: B)
+    where
+        B: Fn(nonblocking::CliFuture<()>),
+    {
+        with_executor(ScriptExecutor(VERSION), || {
+            block_on(Box::pin(async {
+                let version = nonblocking::version().await.unwrap();
+                assert_eq!(version, Version::new(3, 12, 3));
+                Ok(())
+            }))
+        });
+
+        let started = Instant::now();
+
+        with_executor(ScriptExecutor(HANGING), || {
+            let status = nordvpn::with_timeout(Duration::from_millis(100), nonblocking::status);
+
+            block_on(Box::pin(async {
+                assert!(matches!(status.await, Err(CliError::TimedOut(_))));
+                Ok(())
+            }))
+        });
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        with_executor(ProcessExecutor::new("/nonexistent/nordvpn"), || {
+            block_on(Box::pin(async {
+                let diagnosis = nonblocking::diagnose().await.unwrap();
+                assert_eq!(diagnosis, Diagnosis::NotInstalled);
+                Ok(())
+            }))
+        });
+    }
+
+    #[test]
+    fn test_nonblocking() {
+        let temp_dir = TempDir::new("test_nonblocking");
+
+        with_executor(temp_dir.simulator(), || {
+            block_on(async {
+                let connected = nonblocking::connect(None).await.unwrap();
+                let status = nonblocking::status().await.unwrap().into_status().unwrap();
+                assert_eq!(connected.hostname, status.hostname);
+
+                let mut settings = nonblocking::settings().await.unwrap();
+                settings.set_killswitch_async(true).await.unwrap();
+                assert_eq!(settings, nordvpn::settings().unwrap());
+
+                assert!(nonblocking::disconnect().await.unwrap());
+
+                let cancel = CancelHandle::new();
+                cancel.cancel();
+                let status = nordvpn::with_cancel(&cancel, nonblocking::status);
+                assert!(matches!(status.await, Err(CliError::Cancelled(_))));
+            })
+        });
+    }
+
+    #[test]
+    fn test_with_notices() {
+        with_executor(ScriptExecutor(OUTDATED), || {
+            let ((countries, notices), outer) = nordvpn::with_notices(|| {
+                block_on(nonblocking::with_notices(nonblocking::countries()))
+            });
+
+            assert_eq!(countries.unwrap(), ["Germany", "Sweden"]);
+            assert_eq!(
+                notices,
+                [Notice::UpdateAvailable(
+                    "A new version of NordVPN is available! Please update the application."
+                        .to_owned()
+                )]
+            );
+            assert!(outer.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_worker_pool() {
+        check_processes(|future| block_on(future).unwrap());
+    }
+
+    #[test]
+    fn test_main_context() {
+        check_processes(|future| {
+            let context = glib::MainContext::new();
+
+            context
+                .with_thread_default(|| context.block_on(owned(future)))
+                .unwrap()
+        });
+    }
+
+    #[test]
+    fn test_main_context_dropped() {
+        let context = glib::MainContext::new();
+        let mut version = with_executor(ScriptExecutor(VERSION), nonblocking::version);
+
+        // The command is queued on the context, which is destroyed before it
+        // is ever iterated.
+        context.with_thread_default(|| {
+            let mut poll = Context::from_waker(Waker::noop());
+            assert!(version.as_mut().poll(&mut poll).is_pending());
+        });
+        drop(context);
+
+        assert!(matches!(block_on(version), Err(CliError::Cancelled(_))));
+    }
+
+    // Checks that the commands were run while the context was owned, which is
+    // what makes them run as its tasks.
+    async fn owned<F: Future>(future: F) -> F::Output {
+        assert!(glib::MainContext::ref_thread_default().is_owner());
+        future.await
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_tokio() {
+        check_processes(|future| {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(future)
+                .unwrap()
+        });
+    }
+}
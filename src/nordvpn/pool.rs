@@ -0,0 +1,145 @@
+use once_cell::sync::Lazy;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+// Enough for a few blocking calls to wait on the daemon at once, the rest are
+// queued instead of each getting a thread of its own.
+const WORKERS: usize = 4;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+static JOBS: Lazy<Sender<Job>> = Lazy::new(|| {
+    let (sender, receiver) = mpsc::channel::<Job>();
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    for index in 0..WORKERS {
+        let receiver = receiver.clone();
+
+        thread::Builder::new()
+            .name(format!("nordvpn-worker-{}", index))
+            .spawn(move || work(&receiver))
+            .expect("failed to start a worker thread");
+    }
+
+    sender
+});
+
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = receiver.lock().unwrap().recv();
+
+        match job {
+            Ok(job) => job(),
+            Err(_) => return,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+// Resolves to what its `Completer` is given, and re-raises a panic from a job
+// of the pool.
+#[derive(Debug)]
+pub(crate) struct Completion<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Completer<T> {
+    slot: Option<Arc<Mutex<Slot<T>>>>,
+    dropped: Option<fn() -> T>,
+}
+
+// The result of a task that is dropped before it completes, such as one left on
+// a main context that is destroyed, is made by `dropped`, so that its
+// `Completion` does not stay pending forever.
+pub(crate) fn completion<T>(dropped: fn() -> T) -> (Completer<T>, Completion<T>) {
+    let (mut completer, completion) = pair();
+
+    completer.dropped = Some(dropped);
+
+    (completer, completion)
+}
+
+fn pair<T>() -> (Completer<T>, Completion<T>) {
+    let slot = Arc::new(Mutex::new(Slot {
+        result: None,
+        waker: None,
+    }));
+
+    (
+        Completer {
+            slot: Some(slot.clone()),
+            dropped: None,
+        },
+        Completion { slot },
+    )
+}
+
+impl<T> Future for Completion<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<T> {
+        let mut slot = self.slot.lock().unwrap();
+
+        match slot.result.take() {
+            Some(Ok(result)) => Poll::Ready(result),
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                slot.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Completer<T> {
+    pub(crate) fn complete(mut self, result: T) {
+        self.resolve(Ok(result));
+    }
+
+    fn resolve(&mut self, result: thread::Result<T>) {
+        if let Some(slot) = self.slot.take() {
+            let mut slot = slot.lock().unwrap();
+
+            slot.result = Some(result);
+
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        if let Some(dropped) = self.dropped {
+            self.resolve(Ok(dropped()));
+        }
+    }
+}
+
+// Runs `run` on one of the worker threads.
+pub(crate) fn spawn<T, F>(run: F) -> Completion<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (mut completer, completion) = pair();
+
+    JOBS.send(Box::new(move || {
+        completer.resolve(panic::catch_unwind(AssertUnwindSafe(run)));
+    }))
+    .expect("the worker threads have stopped");
+
+    completion
+}
@@ -1,7 +1,9 @@
 use once_cell::sync::Lazy;
-use regex::Regex;
+use regex::{Match, Regex};
+use semver::{Version, VersionReq};
+use std::fmt;
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum RegexError {
     Account,
     AccountEmail,
@@ -12,6 +14,7 @@ pub enum RegexError {
     Countries,
     Groups,
     Login,
+    Register,
     Settings,
     SettingsTechnology,
     SettingsProtocol,
@@ -23,6 +26,8 @@ pub enum RegexError {
     SettingsAutoconnect,
     SettingsIpv6,
     SettingsDns,
+    SettingsWhitelistPorts,
+    SettingsWhitelistSubnets,
     Status,
     StatusHostname,
     StatusCountry,
@@ -31,20 +36,152 @@ pub enum RegexError {
     StatusTechnology,
     StatusProtocol,
     StatusTransfer,
+    StatusUptime,
     Version,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub field: RegexError,
+    pub value: String,
+    pub line: usize,
+    pub column: usize,
+    pub output: String,
+}
+
+impl ParseError {
+    pub fn at(field: RegexError, output: &str, offset: usize, len: usize) -> Self {
+        let before = &output[..offset];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+
+        Self {
+            field,
+            value: output[offset..offset + len].to_owned(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            output: output.to_owned(),
+        }
+    }
+
+    pub fn invalid(field: RegexError, output: &str, value: Match) -> Self {
+        Self::at(field, output, value.start(), value.len())
+    }
+
+    // Points at the first non-blank line that the pattern did not consume, or at
+    // the end of the output when every line matched but the field was absent.
+    pub fn mismatch(field: RegexError, output: &str, regex: &Regex) -> Self {
+        let mut end = 0;
+
+        for found in regex.find_iter(output) {
+            if !output[end..found.start()].trim().is_empty() {
+                break;
+            }
+            end = found.end();
+        }
+
+        let rest = &output[end..];
+        let offset = end + (rest.len() - rest.trim_start().len());
+        let len = output[offset..].find('\n').unwrap_or(output.len() - offset);
+
+        Self::at(field, output, offset, len)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} at line {}, column {}: {:?}",
+            self.field, self.line, self.column, self.value
+        )
+    }
+}
+
+pub static ESCAPE_SEQUENCE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(strings::output::ESCAPE_SEQUENCE).unwrap());
+pub static UPDATE_NOTICE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(strings::output::UPDATE_NOTICE).unwrap());
+
+pub static DAEMON_UNREACHABLE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(strings::output::DAEMON_UNREACHABLE).unwrap());
+pub static PERMISSION_DENIED: Lazy<Regex> =
+    Lazy::new(|| Regex::new(strings::output::PERMISSION_DENIED).unwrap());
+
 pub static WORD_LIST: Lazy<Regex> = Lazy::new(|| Regex::new(strings::WORD_LIST).unwrap());
 
-pub static ACCOUNT: Lazy<Regex> = Lazy::new(|| Regex::new(strings::ACCOUNT).unwrap());
 pub static CONNECT: Lazy<Regex> =
     Lazy::new(|| Regex::new(strings::connect::COUNTRY_SERVER_HOSTNAME).unwrap());
 pub static LOGIN: Lazy<Regex> = Lazy::new(|| Regex::new(strings::login::URL).unwrap());
+pub static LOGIN_CALLBACK: Lazy<Regex> =
+    Lazy::new(|| Regex::new(strings::login::CALLBACK).unwrap());
+pub static LOGIN_INVALID_TOKEN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(strings::login::INVALID_TOKEN).unwrap());
+pub static REGISTER: Lazy<Regex> = Lazy::new(|| Regex::new(strings::register::URL).unwrap());
+pub static REGISTER_PROMPT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(strings::register::PROMPT).unwrap());
+pub static REGISTERED: Lazy<Regex> =
+    Lazy::new(|| Regex::new(strings::register::REGISTERED).unwrap());
 pub static INVALID_SETTING: Lazy<Regex> =
     Lazy::new(|| Regex::new(strings::settings::INVALID_NAME).unwrap());
-pub static SETTINGS: Lazy<Regex> = Lazy::new(|| Regex::new(strings::SETTINGS).unwrap());
-pub static STATUS: Lazy<Regex> = Lazy::new(|| Regex::new(strings::STATUS).unwrap());
 pub static VERSION: Lazy<Regex> = Lazy::new(|| Regex::new(strings::version::VERSION).unwrap());
+pub static WHITELISTED_PORT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(strings::settings::WHITELISTED_PORT).unwrap());
+pub static WHITELIST_OUT_OF_RANGE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(strings::whitelist::OUT_OF_RANGE).unwrap());
+
+#[derive(Debug)]
+pub struct Profile {
+    pub name: &'static str,
+    pub versions: VersionReq,
+    pub account: Regex,
+    pub settings: Regex,
+    pub status: Regex,
+}
+
+impl Profile {
+    fn new(
+        name: &'static str,
+        versions: &str,
+        account: &str,
+        settings: &str,
+        status: &str,
+    ) -> Self {
+        Self {
+            name,
+            versions: VersionReq::parse(versions).unwrap(),
+            account: Regex::new(account).unwrap(),
+            settings: Regex::new(settings).unwrap(),
+            status: Regex::new(status).unwrap(),
+        }
+    }
+
+    pub fn for_version(version: &Version) -> &'static Profile {
+        PROFILES
+            .iter()
+            .find(|profile| profile.versions.matches(version))
+            .unwrap_or_else(|| PROFILES.last().unwrap())
+    }
+}
+
+// Ordered from oldest to newest, unknown versions fall back to the newest.
+pub static PROFILES: Lazy<Vec<Profile>> = Lazy::new(|| {
+    vec![
+        Profile::new(
+            "3.12",
+            "<3.16.0",
+            strings::ACCOUNT,
+            strings::SETTINGS,
+            strings::STATUS,
+        ),
+        Profile::new(
+            "3.16",
+            ">=3.16.0",
+            strings::ACCOUNT,
+            strings::v3_16::SETTINGS,
+            strings::v3_16::STATUS,
+        ),
+    ]
+});
 
 pub mod strings {
     use const_format::*;
@@ -52,13 +189,14 @@ pub mod strings {
     pub const WORD_LIST: &str = r#"(\w+)(?:,\s*|\s*$)"#;
 
     pub const ACCOUNT: &str = formatcp!(
-        r#"(?:{}|{}|{})+"#,
+        r#"(?:{}|{}|{}|{})+"#,
+        account::HEADER,
         account::EMAIL,
         account::ACTIVE,
         account::EXPIRES
     );
     pub const SETTINGS: &str = formatcp!(
-        r#"(?:{}|{}|{}|{}|{}|{}|{}|{}|{}|{})+"#,
+        r#"(?:{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{})+"#,
         settings::TECHNOLOGY,
         settings::PROTOCOL,
         settings::FIREWALL,
@@ -69,9 +207,12 @@ pub mod strings {
         settings::AUTOCONNECT,
         settings::IPV6,
         settings::DNS,
+        settings::WHITELISTED_PORTS,
+        settings::WHITELISTED_SUBNETS,
     );
     pub const STATUS: &str = formatcp!(
-        r#"(?:{}|{}|{}|{}|{}|{}|{}|{})+"#,
+        r#"(?:{}|{}|{}|{}|{}|{}|{}|{}|{})+"#,
+        status::STATE,
         status::HOSTNAME,
         status::COUNTRY,
         status::CITY,
@@ -82,12 +223,22 @@ pub mod strings {
         status::UPTIME
     );
 
+    pub mod output {
+        pub const ESCAPE_SEQUENCE: &str =
+            r#"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[@-Z\\-_]"#;
+        pub const UPDATE_NOTICE: &str = r#"(?i)^\s*A new version of NordVPN is available"#;
+        pub const DAEMON_UNREACHABLE: &str = r#"(?i)Cannot reach System Daemon"#;
+        pub const PERMISSION_DENIED: &str = r#"(?i)Permission denied"#;
+    }
+
     pub mod shared {
         pub const LINE_END_OR_NEWLINE: &str = r#"\s*(?:\n|$)"#;
         pub const IPV4_OR_IPV6: &str =
             r#"(?P<GROUP_NAME>(?i)(?:[\da-f]{0,4}:){1,7}[\da-f]{0,4}|(?:\d{1,3}\.){3}\d{1,3})"#;
         pub const OPENVPN_OR_NORDLYNX: &str = r#"(?P<GROUP_NAME>(?i)OPENVPN|NORDLYNX)"#;
         pub const TCP_OR_UDP: &str = r#"(?P<GROUP_NAME>(?i)TCP|UDP)"#;
+        pub const PORT_PROTOCOLS: &str = r#"(?P<GROUP_NAME>(?i)UDP\|TCP|TCP\|UDP|TCP|UDP)"#;
+        pub const IPV4_OR_IPV6_SUBNET: &str = r#"(?P<GROUP_NAME>(?i)(?:[\da-f]{0,4}:){1,7}[\da-f]{0,4}/\d{1,3}|(?:\d{1,3}\.){3}\d{1,3}/\d{1,2})"#;
         pub const ENABLED_OR_DISABLED: &str = r#"(?P<GROUP_NAME>(?i)enabled|disabled)"#;
     }
 
@@ -95,6 +246,7 @@ pub mod strings {
         use super::shared::*;
         use const_format::*;
 
+        pub const HEADER: &str = concatcp!(r#"Account Information:"#, LINE_END_OR_NEWLINE);
         pub const EMAIL: &str = concatcp!(r#"Email Address:\s+(?P<email>.+)"#, LINE_END_OR_NEWLINE);
         pub const ACTIVE: &str = r#"VPN Service:\s+(?P<active>(?i)[a-z]+)\s*"#;
         pub const EXPIRES: &str = r#"\(Expires on\s+(?P<expires_month>(?i)[a-z]{3})\s+(?P<expires_day>\d+)(?i:st|nd|rd|th),\s+(?P<expires_year>\d{4})\)"#;
@@ -112,6 +264,15 @@ pub mod strings {
             r#"Continue in the browser:\s+(?P<url>.+)"#,
             LINE_END_OR_NEWLINE
         );
+        pub const CALLBACK: &str = r#"^nordvpn://[\w\-\.]+(?:/[^\s?#]*)?(?:\?\S*)?$"#;
+        pub const INVALID_TOKEN: &str =
+            r#"We couldn't log you in - the access token is not valid\."#;
+    }
+
+    pub mod register {
+        pub const URL: &str = super::login::URL;
+        pub const PROMPT: &str = r#"(?m)^\s*(?:Email|Password):"#;
+        pub const REGISTERED: &str = r#"Account has been successfully registered\."#;
     }
 
     pub mod settings {
@@ -158,6 +319,7 @@ pub mod strings {
         pub const AUTOCONNECT: &str = concatcp!(
             r#"Auto-connect:\s+"#,
             str_replace!(ENABLED_OR_DISABLED, "GROUP_NAME", "autoconnect"),
+            r#"(?:[ \t]+(?P<autoconnect_target>[^\n]*?))?"#,
             LINE_END_OR_NEWLINE
         );
         pub const IPV6: &str = concatcp!(
@@ -173,12 +335,27 @@ pub mod strings {
             str_replace!(IPV4_OR_IPV6, "GROUP_NAME", "dns_tertiary"),
             LINE_END_OR_NEWLINE
         );
+        pub const WHITELISTED_PORT: &str = concatcp!(
+            r#"(?P<start>\d+)(?:\s+-\s+(?P<end>\d+))?\s+\("#,
+            str_replace!(PORT_PROTOCOLS, "GROUP_NAME", "protocol"),
+            r#"\)"#
+        );
+        pub const WHITELISTED_PORTS: &str = formatcp!(
+            r#"Whitelisted ports:\s*\n(?P<whitelisted_ports>(?:[ \t]+\d+(?:\s+-\s+\d+)?\s+\({}\)[ \t]*(?:\n|$))+)"#,
+            str_replace!(PORT_PROTOCOLS, "?P<GROUP_NAME>", "?:"),
+        );
+        pub const WHITELISTED_SUBNETS: &str = formatcp!(
+            r#"Whitelisted subnets:\s*\n(?P<whitelisted_subnets>(?:[ \t]+{}[ \t]*(?:\n|$))+)"#,
+            str_replace!(IPV4_OR_IPV6_SUBNET, "?P<GROUP_NAME>", "?:"),
+        );
     }
 
     pub mod status {
         use super::shared::*;
         use const_format::*;
 
+        pub const STATE: &str =
+            concatcp!(r#"Status:\s+(?P<state>(?i)[a-z]+)"#, LINE_END_OR_NEWLINE);
         pub const HOSTNAME: &str = concatcp!(
             r#"Current server:\s+(?P<hostname>[\w\d\-\.]+)"#,
             LINE_END_OR_NEWLINE
@@ -216,6 +393,52 @@ pub mod strings {
         );
     }
 
+    pub mod v3_16 {
+        use super::{settings, status};
+        use const_format::*;
+
+        pub const SETTINGS: &str = formatcp!(
+            r#"(?:{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{})+"#,
+            settings::TECHNOLOGY,
+            settings::PROTOCOL,
+            settings::FIREWALL,
+            settings::KILLSWITCH,
+            str_replace!(settings::CYBERSEC, "CyberSec:", "Threat Protection Lite:"),
+            settings::OBFUSCATE,
+            settings::NOTIFY,
+            settings::AUTOCONNECT,
+            settings::IPV6,
+            settings::DNS,
+            str_replace!(
+                settings::WHITELISTED_PORTS,
+                "Whitelisted ports:",
+                "(?:Allowlisted|Whitelisted) ports:"
+            ),
+            str_replace!(
+                settings::WHITELISTED_SUBNETS,
+                "Whitelisted subnets:",
+                "(?:Allowlisted|Whitelisted) subnets:"
+            ),
+        );
+        pub const STATUS: &str = formatcp!(
+            r#"(?:{}|{}|{}|{}|{}|{}|{}|{}|{})+"#,
+            status::STATE,
+            str_replace!(status::HOSTNAME, "Current server:", "Hostname:"),
+            status::COUNTRY,
+            status::CITY,
+            str_replace!(status::IP, "Server IP:", "IP:"),
+            status::TECHNOLOGY,
+            status::PROTOCOL,
+            status::TRANSFER,
+            status::UPTIME
+        );
+    }
+
+    pub mod whitelist {
+        pub const OUT_OF_RANGE: &str =
+            r#"Port (?P<port>\d+) value is out of range \[(?P<min>\d+) - (?P<max>\d+)\]"#;
+    }
+
     pub mod version {
         use super::shared::*;
         use const_format::*;
@@ -250,4 +473,26 @@ mod tests {
     fn print_status_pattern() {
         println!("Status Pattern: {}", super::strings::STATUS);
     }
+
+    #[test]
+    fn locate_mismatch() {
+        use super::{ParseError, RegexError, PROFILES};
+
+        let output =
+            "Status: Connected\nCurrent server: se497.nordvpn.com\nCountry: Sweden\nCity: ???\n";
+        let error = ParseError::mismatch(RegexError::StatusCity, output, &PROFILES[0].status);
+
+        assert_eq!((error.line, error.column), (4, 1));
+        assert_eq!(error.value, "City: ???");
+    }
+
+    #[test]
+    fn select_profile() {
+        use super::Profile;
+        use semver::Version;
+
+        assert_eq!(Profile::for_version(&Version::new(3, 12, 3)).name, "3.12");
+        assert_eq!(Profile::for_version(&Version::new(3, 16, 1)).name, "3.16");
+        assert_eq!(Profile::for_version(&Version::new(4, 0, 0)).name, "3.16");
+    }
 }
@@ -0,0 +1,1131 @@
+use super::cli::{Protocol, Technology};
+use super::executor::{Executor, Limits};
+use byte_unit::Byte;
+use chrono::{Datelike, NaiveDate};
+use semver::Version;
+use std::env;
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const STATE_DIR_VAR: &str = "NORDVPN_SIMULATOR_DIR";
+
+const STATE_FILE: &str = "state";
+
+// Every invocation reads and rewrites the state file, so concurrent callers
+// (such as a status watcher polling alongside a settings change) would
+// otherwise lose each other's updates.
+static RUN_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimulatedServer {
+    pub hostname: &'static str,
+    pub country: &'static str,
+    pub country_code: &'static str,
+    pub city: &'static str,
+    pub ip: &'static str,
+    pub groups: &'static [&'static str],
+}
+
+pub const SERVERS: &[SimulatedServer] = &[
+    SimulatedServer {
+        hostname: "us8412.nordvpn.com",
+        country: "United States",
+        country_code: "US",
+        city: "New York",
+        ip: "192.0.2.12",
+        groups: &["Standard_VPN_Servers", "P2P", "The_Americas"],
+    },
+    SimulatedServer {
+        hostname: "us9120.nordvpn.com",
+        country: "United States",
+        country_code: "US",
+        city: "Los Angeles",
+        ip: "192.0.2.20",
+        groups: &["Standard_VPN_Servers", "The_Americas"],
+    },
+    SimulatedServer {
+        hostname: "de1021.nordvpn.com",
+        country: "Germany",
+        country_code: "DE",
+        city: "Berlin",
+        ip: "198.51.100.21",
+        groups: &["Standard_VPN_Servers", "P2P", "Europe"],
+    },
+    SimulatedServer {
+        hostname: "de1108.nordvpn.com",
+        country: "Germany",
+        country_code: "DE",
+        city: "Frankfurt",
+        ip: "198.51.100.108",
+        groups: &["Standard_VPN_Servers", "Double_VPN", "Europe"],
+    },
+    SimulatedServer {
+        hostname: "se497.nordvpn.com",
+        country: "Sweden",
+        country_code: "SE",
+        city: "Stockholm",
+        ip: "198.51.100.197",
+        groups: &["Standard_VPN_Servers", "P2P", "Europe"],
+    },
+    SimulatedServer {
+        hostname: "uk2214.nordvpn.com",
+        country: "United Kingdom",
+        country_code: "GB",
+        city: "London",
+        ip: "203.0.113.14",
+        groups: &["Standard_VPN_Servers", "Onion_Over_VPN", "Europe"],
+    },
+    SimulatedServer {
+        hostname: "ch350.nordvpn.com",
+        country: "Switzerland",
+        country_code: "CH",
+        city: "Zurich",
+        ip: "203.0.113.50",
+        groups: &["Standard_VPN_Servers", "Obfuscated_Servers", "Europe"],
+    },
+    SimulatedServer {
+        hostname: "jp612.nordvpn.com",
+        country: "Japan",
+        country_code: "JP",
+        city: "Tokyo",
+        ip: "203.0.113.112",
+        groups: &["Standard_VPN_Servers", "P2P", "Asia_Pacific"],
+    },
+    SimulatedServer {
+        hostname: "au601.nordvpn.com",
+        country: "Australia",
+        country_code: "AU",
+        city: "Sydney",
+        ip: "203.0.113.161",
+        groups: &["Standard_VPN_Servers", "Asia_Pacific"],
+    },
+];
+
+pub const GROUPS: &[&str] = &[
+    "Africa_The_Middle_East_And_India",
+    "Asia_Pacific",
+    "Double_VPN",
+    "Europe",
+    "Obfuscated_Servers",
+    "Onion_Over_VPN",
+    "P2P",
+    "Standard_VPN_Servers",
+    "The_Americas",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatorState {
+    pub version: String,
+    pub daemon_running: bool,
+    pub socket_accessible: bool,
+    pub email: Option<String>,
+    pub expires: NaiveDate,
+    pub connected: Option<String>,
+    pub connected_at: u64,
+    pub phase: Option<String>,
+    pub rating: Option<u8>,
+    pub rateable: bool,
+    // Older daemons ask for the email and password of a new account on the
+    // terminal instead of handing out a sign-up URL.
+    pub register_prompts: bool,
+    pub technology: Technology,
+    pub protocol: Protocol,
+    pub firewall: bool,
+    pub killswitch: bool,
+    pub cybersec: bool,
+    pub obfuscate: bool,
+    pub notify: bool,
+    pub autoconnect: bool,
+    pub autoconnect_target: Vec<String>,
+    pub ipv6: bool,
+    pub dns: Vec<IpAddr>,
+    pub whitelist_ports: Vec<(u16, u16, Option<Protocol>)>,
+    pub whitelist_subnets: Vec<String>,
+}
+
+impl Default for SimulatorState {
+    fn default() -> Self {
+        Self {
+            version: "3.12.3".to_owned(),
+            daemon_running: true,
+            socket_accessible: true,
+            email: Some("user@example.com".to_owned()),
+            expires: NaiveDate::from_ymd_opt(2023, 3, 5).unwrap(),
+            connected: None,
+            connected_at: 0,
+            phase: None,
+            rating: None,
+            rateable: false,
+            register_prompts: false,
+            technology: Technology::NordLynx,
+            protocol: Protocol::Udp,
+            firewall: true,
+            killswitch: false,
+            cybersec: false,
+            obfuscate: false,
+            notify: true,
+            autoconnect: false,
+            autoconnect_target: Vec::new(),
+            ipv6: false,
+            dns: Vec::new(),
+            whitelist_ports: Vec::new(),
+            whitelist_subnets: Vec::new(),
+        }
+    }
+}
+
+impl SimulatorState {
+    // Daemon releases from 3.16 onwards renamed several labels.
+    fn since_3_16(&self) -> bool {
+        matches!(self.version.parse::<Version>(), Ok(version) if version >= Version::new(3, 16, 0))
+    }
+
+    fn dialect<'a>(&self, before_3_16: &'a str, since_3_16: &'a str) -> &'a str {
+        if self.since_3_16() {
+            since_3_16
+        } else {
+            before_3_16
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+
+        let _ = writeln!(text, "version={}", self.version);
+        let _ = writeln!(text, "daemon_running={}", self.daemon_running);
+        let _ = writeln!(text, "socket_accessible={}", self.socket_accessible);
+        let _ = writeln!(text, "email={}", self.email.as_deref().unwrap_or(""));
+        let _ = writeln!(text, "expires={}", self.expires);
+        let _ = writeln!(
+            text,
+            "connected={}",
+            self.connected.as_deref().unwrap_or("")
+        );
+        let _ = writeln!(text, "connected_at={}", self.connected_at);
+        if let Some(phase) = &self.phase {
+            let _ = writeln!(text, "phase={}", phase);
+        }
+        if let Some(rating) = self.rating {
+            let _ = writeln!(text, "rating={}", rating);
+        }
+        let _ = writeln!(text, "rateable={}", self.rateable);
+        let _ = writeln!(text, "register_prompts={}", self.register_prompts);
+        let _ = writeln!(text, "technology={}", self.technology);
+        let _ = writeln!(text, "protocol={}", self.protocol);
+        let _ = writeln!(text, "firewall={}", self.firewall);
+        let _ = writeln!(text, "killswitch={}", self.killswitch);
+        let _ = writeln!(text, "cybersec={}", self.cybersec);
+        let _ = writeln!(text, "obfuscate={}", self.obfuscate);
+        let _ = writeln!(text, "notify={}", self.notify);
+        let _ = writeln!(text, "autoconnect={}", self.autoconnect);
+        if !self.autoconnect_target.is_empty() {
+            let _ = writeln!(
+                text,
+                "autoconnect_target={}",
+                self.autoconnect_target.join(" ")
+            );
+        }
+        let _ = writeln!(text, "ipv6={}", self.ipv6);
+        for address in &self.dns {
+            let _ = writeln!(text, "dns={}", address);
+        }
+        for (start, end, protocol) in &self.whitelist_ports {
+            let protocol = protocol.map_or_else(String::new, |protocol| protocol.to_string());
+            let _ = writeln!(text, "whitelist_port={}-{}-{}", start, end, protocol);
+        }
+        for subnet in &self.whitelist_subnets {
+            let _ = writeln!(text, "whitelist_subnet={}", subnet);
+        }
+
+        text
+    }
+
+    fn from_text(text: &str) -> Option<Self> {
+        let mut state = Self {
+            email: None,
+            ..Self::default()
+        };
+
+        for line in text.lines() {
+            let (key, value) = line.split_once('=')?;
+
+            match key {
+                "version" => state.version = value.to_owned(),
+                "daemon_running" => state.daemon_running = value.parse().ok()?,
+                "socket_accessible" => state.socket_accessible = value.parse().ok()?,
+                "email" => state.email = Some(value.to_owned()).filter(|email| !email.is_empty()),
+                "expires" => state.expires = value.parse().ok()?,
+                "connected" => {
+                    state.connected = Some(value.to_owned()).filter(|host| !host.is_empty())
+                }
+                "connected_at" => state.connected_at = value.parse().ok()?,
+                "phase" => state.phase = Some(value.to_owned()),
+                "rating" => state.rating = Some(value.parse().ok()?),
+                "rateable" => state.rateable = value.parse().ok()?,
+                "register_prompts" => state.register_prompts = value.parse().ok()?,
+                "technology" => state.technology = value.parse().ok()?,
+                "protocol" => state.protocol = value.parse().ok()?,
+                "firewall" => state.firewall = value.parse().ok()?,
+                "killswitch" => state.killswitch = value.parse().ok()?,
+                "cybersec" => state.cybersec = value.parse().ok()?,
+                "obfuscate" => state.obfuscate = value.parse().ok()?,
+                "notify" => state.notify = value.parse().ok()?,
+                "autoconnect" => state.autoconnect = value.parse().ok()?,
+                "autoconnect_target" => {
+                    state.autoconnect_target = value.split(' ').map(str::to_owned).collect()
+                }
+                "ipv6" => state.ipv6 = value.parse().ok()?,
+                "dns" => state.dns.push(value.parse().ok()?),
+                "whitelist_port" => {
+                    let mut parts = value.splitn(3, '-');
+                    let start = parts.next()?.parse().ok()?;
+                    let end = parts.next()?.parse().ok()?;
+                    let protocol = match parts.next()? {
+                        "" => None,
+                        protocol => Some(protocol.parse().ok()?),
+                    };
+                    state.whitelist_ports.push((start, end, protocol));
+                }
+                "whitelist_subnet" => state.whitelist_subnets.push(value.to_owned()),
+                _ => return None,
+            }
+        }
+
+        Some(state)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    state_dir: PathBuf,
+}
+
+impl Simulator {
+    pub fn new<P: Into<PathBuf>>(state_dir: P) -> Self {
+        Self {
+            state_dir: state_dir.into(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        match env::var_os(STATE_DIR_VAR) {
+            Some(state_dir) => Self::new(state_dir),
+            None => Self::new(env::temp_dir().join("nordvpn-simulator")),
+        }
+    }
+
+    pub fn state_dir(&self) -> &Path {
+        &self.state_dir
+    }
+
+    pub fn state(&self) -> io::Result<SimulatorState> {
+        match fs::read_to_string(self.state_dir.join(STATE_FILE)) {
+            Ok(text) => SimulatorState::from_text(&text).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "malformed simulator state")
+            }),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(SimulatorState::default()),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn set_state(&self, state: &SimulatorState) -> io::Result<()> {
+        fs::create_dir_all(&self.state_dir)?;
+
+        // Readers never see a partially written file.
+        let temp = self
+            .state_dir
+            .join(format!("{}.{}", STATE_FILE, std::process::id()));
+        fs::write(&temp, state.to_text())?;
+        fs::rename(temp, self.state_dir.join(STATE_FILE))
+    }
+
+    pub fn run<S, I>(&self, args: I) -> io::Result<Output>
+    where
+        S: AsRef<OsStr>,
+        I: IntoIterator<Item = S>,
+    {
+        self.run_with_input(args, &[])
+    }
+
+    pub fn run_with_input<S, I>(&self, args: I, input: &[u8]) -> io::Result<Output>
+    where
+        S: AsRef<OsStr>,
+        I: IntoIterator<Item = S>,
+    {
+        let args = args
+            .into_iter()
+            .map(|arg| arg.as_ref().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let _guard = RUN_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut state = self.state()?;
+        let input = String::from_utf8_lossy(input);
+        let (code, stdout) = respond(&mut state, &args, &input, now());
+        self.set_state(&state)?;
+
+        Ok(Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.into_bytes(),
+            stderr: Vec::new(),
+        })
+    }
+}
+
+impl Executor for Simulator {
+    fn output(&self, command: &mut Command, _limits: &Limits) -> io::Result<Output> {
+        self.run(command.get_args())
+    }
+
+    fn output_with_input(
+        &self,
+        command: &mut Command,
+        input: &[u8],
+        _limits: &Limits,
+    ) -> io::Result<Output> {
+        self.run_with_input(command.get_args(), input)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn success<S: Into<String>>(text: S) -> (i32, String) {
+    (0, text.into() + "\n")
+}
+
+fn failure<S: Into<String>>(text: S) -> (i32, String) {
+    (1, text.into() + "\n")
+}
+
+fn invalid_command() -> (i32, String) {
+    failure("The command you entered is not valid.")
+}
+
+fn respond(state: &mut SimulatorState, args: &[&str], input: &str, now: u64) -> (i32, String) {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (*command, args),
+        None => return invalid_command(),
+    };
+
+    let local = matches!(command, "version" | "--version" | "-v");
+
+    if !local && !state.socket_accessible {
+        return failure("Permission denied accessing /run/nordvpn/nordvpnd.sock");
+    } else if !local && !state.daemon_running {
+        return failure("Whoops! Cannot reach System Daemon.");
+    }
+
+    match command {
+        "account" => account(state),
+        "cities" => cities(args),
+        "connect" | "c" => connect(state, args, now),
+        "countries" => success(list(countries())),
+        "disconnect" | "d" => disconnect(state),
+        "groups" => success(list(GROUPS.iter().copied())),
+        "login" => login(state, args),
+        "logout" => logout(state),
+        "rate" => rate(state, args),
+        "register" => register(state, args, input),
+        "set" | "s" => set(state, args),
+        "settings" => settings(state),
+        "status" => status(state, now),
+        "whitelist" => whitelist(state, args),
+        "version" | "--version" | "-v" => success(format!("NordVPN Version {}", state.version)),
+        command => failure(format!("Command '{}' doesn't exist.", command)),
+    }
+}
+
+fn list<'a, I: IntoIterator<Item = &'a str>>(items: I) -> String {
+    items
+        .into_iter()
+        .map(|item| item.replace(' ', "_"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn countries() -> Vec<&'static str> {
+    let mut countries = SERVERS
+        .iter()
+        .map(|server| server.country)
+        .collect::<Vec<_>>();
+    countries.sort_unstable();
+    countries.dedup();
+    countries
+}
+
+fn matches(name: &str, token: &str) -> bool {
+    name.replace(' ', "_")
+        .eq_ignore_ascii_case(&token.replace(' ', "_"))
+}
+
+fn ordinal(day: u32) -> &'static str {
+    match (day % 10, day % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    }
+}
+
+fn enabled(value: bool) -> &'static str {
+    if value {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "enabled" | "on" | "1" => Some(true),
+        "false" | "disabled" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn account(state: &SimulatorState) -> (i32, String) {
+    let email = match &state.email {
+        Some(email) => email,
+        None => return failure("You are not logged in."),
+    };
+
+    success(format!(
+        "Account Information:\nEmail Address: {}\nVPN Service: Active (Expires on {} {}{}, {})",
+        email,
+        state.expires.format("%b"),
+        state.expires.day(),
+        ordinal(state.expires.day()),
+        state.expires.year(),
+    ))
+}
+
+fn cities(args: &[&str]) -> (i32, String) {
+    let country = match args {
+        [country] => country,
+        _ => return invalid_command(),
+    };
+
+    let mut cities = SERVERS
+        .iter()
+        .filter(|server| matches(server.country, country))
+        .map(|server| server.city)
+        .collect::<Vec<_>>();
+
+    if cities.is_empty() {
+        return failure("Servers are not available in this country.");
+    }
+
+    cities.sort_unstable();
+    cities.dedup();
+
+    success(list(cities))
+}
+
+fn select_server(args: &[&str]) -> Option<&'static SimulatedServer> {
+    let (group, args) = match args {
+        ["--group", group, args @ ..] | ["-g", group, args @ ..] => (Some(*group), args),
+        args => (None, args),
+    };
+
+    if let Some(group) = group {
+        if !GROUPS.iter().any(|name| matches(name, group)) {
+            return None;
+        }
+    }
+
+    let in_group = |server: &&SimulatedServer| {
+        group.is_none_or(|group| server.groups.iter().any(|name| matches(name, group)))
+    };
+
+    let mut servers = SERVERS.iter().filter(in_group);
+
+    match args {
+        [] => servers.next(),
+        [token] => servers.find(|server| {
+            matches(server.country, token)
+                || server.country_code.eq_ignore_ascii_case(token)
+                || (group.is_none() && matches(server.city, token))
+                || (group.is_none() && server.groups.iter().any(|name| matches(name, token)))
+                || (group.is_none()
+                    && server.hostname.split('.').next().unwrap() == token.to_lowercase())
+                || (group.is_none() && server.hostname == token.to_lowercase())
+        }),
+        [country, city] if group.is_none() => {
+            servers.find(|server| matches(server.country, country) && matches(server.city, city))
+        }
+        _ => None,
+    }
+}
+
+fn connect(state: &mut SimulatorState, args: &[&str], now: u64) -> (i32, String) {
+    if state.email.is_none() {
+        return failure("You are not logged in.");
+    }
+
+    let server = match select_server(args) {
+        Some(server) => server,
+        None => return failure("The specified server is not available at the moment or does not support your connection settings."),
+    };
+
+    state.connected = Some(server.hostname.to_owned());
+    state.connected_at = now;
+    state.phase = None;
+    state.rating = None;
+    state.rateable = true;
+
+    let description = format!(
+        "{} #{} ({})",
+        server.country,
+        server
+            .hostname
+            .trim_start_matches(char::is_alphabetic)
+            .split('.')
+            .next()
+            .unwrap(),
+        server.hostname
+    );
+
+    success(format!(
+        "Connecting to {}\nYou are connected to {}!",
+        description, description
+    ))
+}
+
+fn disconnect(state: &mut SimulatorState) -> (i32, String) {
+    if state.connected.take().is_none() {
+        return success("You are not connected to NordVPN.");
+    }
+
+    success("You are disconnected from NordVPN.\nHow would you rate your connection quality on a scale from 1 (poor) to 5 (excellent)? Type 'nordvpn rate [1-5]'.")
+}
+
+fn login(state: &mut SimulatorState, args: &[&str]) -> (i32, String) {
+    if state.email.is_some() {
+        return failure("You are already logged in.");
+    }
+
+    match args {
+        [] => {
+            success("Continue in the browser: https://ucp.nordvpn.com/login/?challenge=simulated")
+        }
+        ["--callback", url] => {
+            if !url.starts_with("nordvpn://login?") || !url.contains("exchange_token=") {
+                return failure("The provided callback URL is invalid.");
+            }
+            state.email = Some("user@example.com".to_owned());
+            success("Welcome to NordVPN! You can now connect to VPN by using 'nordvpn connect'.")
+        }
+        ["--token", token] => {
+            if token.len() < 8 || !token.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return failure(
+                    "We couldn't log you in - the access token is not valid. Please check if you've entered the token correctly. If the issue persists, contact our customer support.",
+                );
+            }
+            state.email = Some("user@example.com".to_owned());
+            success("Welcome to NordVPN! You can now connect to VPN by using 'nordvpn connect'.")
+        }
+        ["--username", username, "--password", _] => {
+            state.email = Some((*username).to_owned());
+            success("Welcome to NordVPN! You can now connect to VPN by using 'nordvpn connect'.")
+        }
+        _ => invalid_command(),
+    }
+}
+
+fn logout(state: &mut SimulatorState) -> (i32, String) {
+    if state.email.take().is_none() {
+        return failure("You are not logged in.");
+    }
+
+    state.connected = None;
+    state.phase = None;
+
+    success("You are logged out.")
+}
+
+fn rate(state: &mut SimulatorState, args: &[&str]) -> (i32, String) {
+    let rating = match args {
+        [rating] => match rating.parse::<u8>() {
+            Ok(rating) if (1..=5).contains(&rating) => rating,
+            _ => return failure("Rating must be an integer between 1 and 5."),
+        },
+        _ => return invalid_command(),
+    };
+
+    if !state.rateable {
+        return failure("You have not made a connection to VPN server yet. Please connect to the server and try again.");
+    } else if state.rating.is_some() {
+        return failure("You have already provided a rating for your active/previous connection.");
+    }
+
+    state.rating = Some(rating);
+
+    success("Thank you for your feedback!")
+}
+
+fn register(state: &SimulatorState, args: &[&str], input: &str) -> (i32, String) {
+    if state.email.is_some() {
+        return failure("You are already logged in.");
+    } else if !args.is_empty() {
+        return invalid_command();
+    } else if !state.register_prompts {
+        return success(
+            "Continue in the browser: https://ucp.nordvpn.com/signup/?challenge=simulated",
+        );
+    }
+
+    // The prompts are written without a newline and the answers are read from
+    // standard input, so running out of input ends the command like a closed
+    // terminal would.
+    let mut answers = input.lines().map(str::trim);
+    let mut prompts = "Email: ".to_owned();
+
+    let email = match answers.next() {
+        Some(email) => email,
+        None => return failure(prompts + "\nEOF"),
+    };
+
+    if !email.contains('@') {
+        return failure(prompts + "\nThe email address is not valid.");
+    }
+
+    prompts += "Password: ";
+
+    let password = match answers.next() {
+        Some(password) => password,
+        None => return failure(prompts + "\nEOF"),
+    };
+
+    if password.len() < 8 {
+        return failure(prompts + "\nPassword must be at least 8 characters long.");
+    }
+
+    success(prompts + "\nAccount has been successfully registered.")
+}
+
+fn set(state: &mut SimulatorState, args: &[&str]) -> (i32, String) {
+    let (setting, values) = match args.split_first() {
+        Some((setting, values)) => (*setting, values),
+        None => return invalid_command(),
+    };
+
+    let (name, value) = match (setting.to_lowercase().as_str(), values) {
+        ("technology", [value]) => match value.parse::<Technology>() {
+            Ok(technology) => {
+                state.technology = technology;
+                ("Technology", technology.to_string())
+            }
+            Err(_) => return invalid_command(),
+        },
+        ("protocol", [value]) => match value.parse::<Protocol>() {
+            Ok(protocol) => {
+                state.protocol = protocol;
+                ("Protocol", protocol.to_string())
+            }
+            Err(_) => return invalid_command(),
+        },
+        ("dns", [value]) if parse_bool(value) == Some(false) => {
+            state.dns.clear();
+            ("DNS", enabled(false).to_owned())
+        }
+        ("dns", addresses) if (1..=3).contains(&addresses.len()) => {
+            let addresses = match addresses
+                .iter()
+                .map(|address| address.parse::<IpAddr>())
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(addresses) => addresses,
+                Err(_) => return invalid_command(),
+            };
+            state.cybersec = false;
+            state.dns = addresses;
+            ("DNS", list(values.iter().copied()))
+        }
+        (
+            setting @ ("firewall" | "killswitch" | "cybersec" | "obfuscate" | "notify"
+            | "autoconnect" | "ipv6"),
+            [value],
+        ) => {
+            let value = match parse_bool(value) {
+                Some(value) => value,
+                None => return invalid_command(),
+            };
+            let (name, field) = match setting {
+                "firewall" => ("Firewall", &mut state.firewall),
+                "killswitch" => ("Kill Switch", &mut state.killswitch),
+                "cybersec" => ("CyberSec", &mut state.cybersec),
+                "obfuscate" => ("Obfuscate", &mut state.obfuscate),
+                "notify" => ("Notify", &mut state.notify),
+                "autoconnect" => ("Auto-connect", &mut state.autoconnect),
+                _ => ("IPv6", &mut state.ipv6),
+            };
+            *field = value;
+            if setting == "cybersec" && value {
+                state.dns.clear();
+            }
+            if setting == "autoconnect" {
+                state.autoconnect_target.clear();
+            }
+            (name, enabled(value).to_owned())
+        }
+        ("autoconnect", [value, target @ ..])
+            if parse_bool(value) == Some(true) && select_server(target).is_some() =>
+        {
+            state.autoconnect = true;
+            state.autoconnect_target = target.iter().map(|arg| arg.to_string()).collect();
+            ("Auto-connect", enabled(true).to_owned())
+        }
+        (
+            "technology" | "protocol" | "dns" | "firewall" | "killswitch" | "cybersec"
+            | "obfuscate" | "notify" | "autoconnect" | "ipv6",
+            _,
+        ) => return invalid_command(),
+        (setting, _) => return failure(format!("Command '{}' doesn't exist.", setting)),
+    };
+
+    success(format!("{} is set to '{}' successfully.", name, value))
+}
+
+fn settings(state: &SimulatorState) -> (i32, String) {
+    let mut text = String::new();
+
+    let _ = writeln!(text, "Technology: {}", state.technology);
+    if state.technology == Technology::OpenVpn {
+        let _ = writeln!(text, "Protocol: {}", state.protocol);
+    }
+    let _ = writeln!(text, "Firewall: {}", enabled(state.firewall));
+    if state.since_3_16() {
+        let _ = writeln!(text, "Firewall Mark: 0xe1f1");
+        let _ = writeln!(text, "Routing: enabled");
+    }
+    let _ = writeln!(text, "Kill Switch: {}", enabled(state.killswitch));
+    let _ = writeln!(
+        text,
+        "{}: {}",
+        state.dialect("CyberSec", "Threat Protection Lite"),
+        enabled(state.cybersec)
+    );
+    if state.technology == Technology::OpenVpn {
+        let _ = writeln!(text, "Obfuscate: {}", enabled(state.obfuscate));
+    }
+    let _ = writeln!(text, "Notify: {}", enabled(state.notify));
+    if state.autoconnect_target.is_empty() {
+        let _ = writeln!(text, "Auto-connect: {}", enabled(state.autoconnect));
+    } else {
+        let _ = writeln!(
+            text,
+            "Auto-connect: {} {}",
+            enabled(state.autoconnect),
+            state.autoconnect_target.join(" ")
+        );
+    }
+    let _ = writeln!(text, "IPv6: {}", enabled(state.ipv6));
+    if state.since_3_16() {
+        let _ = writeln!(text, "Meshnet: disabled");
+    }
+    if state.dns.is_empty() {
+        let _ = writeln!(text, "DNS: {}", enabled(false));
+    } else {
+        let dns = state
+            .dns
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let _ = writeln!(text, "DNS: {}", dns.join(", "));
+    }
+    if !state.whitelist_ports.is_empty() {
+        let _ = writeln!(
+            text,
+            "{} ports:",
+            state.dialect("Whitelisted", "Allowlisted")
+        );
+        for port in &state.whitelist_ports {
+            let _ = writeln!(text, "\t{}", describe_ports(port));
+        }
+    }
+    if !state.whitelist_subnets.is_empty() {
+        let _ = writeln!(
+            text,
+            "{} subnets:",
+            state.dialect("Whitelisted", "Allowlisted")
+        );
+        for subnet in &state.whitelist_subnets {
+            let _ = writeln!(text, "\t{}", subnet);
+        }
+    }
+
+    (0, text)
+}
+
+fn status(state: &SimulatorState, now: u64) -> (i32, String) {
+    let server = match state
+        .connected
+        .as_deref()
+        .and_then(|hostname| SERVERS.iter().find(|server| server.hostname == hostname))
+    {
+        Some(server) => server,
+        None => return success("Status: Disconnected"),
+    };
+
+    let uptime = now.saturating_sub(state.connected_at);
+    let received = Byte::from_bytes(u128::from(uptime) * 48_731 + 9_216);
+    let sent = Byte::from_bytes(u128::from(uptime) * 7_127 + 4_608);
+
+    let mut text = String::new();
+
+    // A phase such as "Connecting" only reports the fields known so far.
+    let _ = writeln!(
+        text,
+        "Status: {}",
+        state.phase.as_deref().unwrap_or("Connected")
+    );
+    let _ = writeln!(
+        text,
+        "{}: {}",
+        state.dialect("Current server", "Hostname"),
+        server.hostname
+    );
+    let _ = writeln!(text, "Country: {}", server.country);
+    let _ = writeln!(text, "City: {}", server.city);
+    if state.phase.is_some() {
+        return (0, text);
+    }
+    let _ = writeln!(text, "{}: {}", state.dialect("Server IP", "IP"), server.ip);
+    let _ = writeln!(text, "Current technology: {}", state.technology);
+    let _ = writeln!(text, "Current protocol: {}", state.protocol);
+    let _ = writeln!(
+        text,
+        "Transfer: {} received, {} sent",
+        received.get_appropriate_unit(true),
+        sent.get_appropriate_unit(true)
+    );
+    let _ = writeln!(text, "Uptime: {}", describe_uptime(uptime));
+
+    (0, text)
+}
+
+fn describe_uptime(seconds: u64) -> String {
+    let components = [
+        (seconds / 86_400, "day"),
+        (seconds / 3_600 % 24, "hour"),
+        (seconds / 60 % 60, "minute"),
+        (seconds % 60, "second"),
+    ];
+
+    let described = components
+        .iter()
+        .filter(|(value, _)| *value != 0)
+        .map(|(value, unit)| format!("{} {}{}", value, unit, if *value == 1 { "" } else { "s" }))
+        .collect::<Vec<_>>();
+
+    if described.is_empty() {
+        "0 seconds".to_owned()
+    } else {
+        described.join(" ")
+    }
+}
+
+fn describe_protocol(protocol: Option<Protocol>) -> String {
+    protocol.map_or_else(|| "UDP|TCP".to_owned(), |protocol| protocol.to_string())
+}
+
+fn describe_ports(&(start, end, protocol): &(u16, u16, Option<Protocol>)) -> String {
+    if start == end {
+        format!("{:>5} ({})", start, describe_protocol(protocol))
+    } else {
+        format!("{:>5} - {} ({})", start, end, describe_protocol(protocol))
+    }
+}
+
+fn whitelist(state: &mut SimulatorState, args: &[&str]) -> (i32, String) {
+    let (add, args) = match args {
+        ["add", args @ ..] => (true, args),
+        ["remove", args @ ..] => (false, args),
+        _ => return invalid_command(),
+    };
+
+    match args {
+        ["all"] if !add => {
+            state.whitelist_ports.clear();
+            state.whitelist_subnets.clear();
+            success("All ports and subnets have been removed from the whitelist successfully.")
+        }
+        ["subnet", subnet] => whitelist_subnet(state, add, subnet),
+        ["port" | "ports", args @ ..] => {
+            let (ports, protocol) = match args {
+                [ports @ .., "protocol", protocol] => match protocol.parse::<Protocol>() {
+                    Ok(protocol) => (ports, Some(protocol)),
+                    Err(_) => return invalid_command(),
+                },
+                ports => (ports, None),
+            };
+            let ports = match ports
+                .iter()
+                .map(|port| port.parse::<u32>())
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(ports) => ports,
+                Err(_) => return invalid_command(),
+            };
+            if let Some(port) = ports.iter().find(|port| !(1..=65535).contains(*port)) {
+                return failure(format!("Port {} value is out of range [1 - 65535].", port));
+            }
+            let (start, end) = match ports[..] {
+                [port] => (port as u16, port as u16),
+                [start, end] if start <= end => (start as u16, end as u16),
+                _ => return invalid_command(),
+            };
+            whitelist_ports(state, add, (start, end, protocol))
+        }
+        _ => invalid_command(),
+    }
+}
+
+fn whitelist_ports(
+    state: &mut SimulatorState,
+    add: bool,
+    entry: (u16, u16, Option<Protocol>),
+) -> (i32, String) {
+    let (start, end, protocol) = entry;
+    let description = if start == end {
+        format!("Port {} ({}) is", start, describe_protocol(protocol))
+    } else {
+        format!(
+            "Ports {} - {} ({}) are",
+            start,
+            end,
+            describe_protocol(protocol)
+        )
+    };
+    let position = state
+        .whitelist_ports
+        .iter()
+        .position(|existing| *existing == entry);
+
+    match (add, position) {
+        (true, Some(_)) => failure(format!("{} already whitelisted.", description)),
+        (true, None) => {
+            state.whitelist_ports.push(entry);
+            success(format!("{} whitelisted successfully.", description))
+        }
+        (false, Some(position)) => {
+            state.whitelist_ports.remove(position);
+            success(format!(
+                "{} removed from whitelist successfully.",
+                description
+            ))
+        }
+        (false, None) => failure(format!("{} not whitelisted.", description)),
+    }
+}
+
+fn whitelist_subnet(state: &mut SimulatorState, add: bool, subnet: &str) -> (i32, String) {
+    let valid = subnet.split_once('/').is_some_and(|(address, prefix)| {
+        match (address.parse::<IpAddr>(), prefix.parse::<u8>()) {
+            (Ok(IpAddr::V4(_)), Ok(prefix)) => prefix <= 32,
+            (Ok(IpAddr::V6(_)), Ok(prefix)) => prefix <= 128,
+            _ => false,
+        }
+    });
+
+    if !valid {
+        return invalid_command();
+    }
+
+    let position = state
+        .whitelist_subnets
+        .iter()
+        .position(|existing| existing == subnet);
+
+    match (add, position) {
+        (true, Some(_)) => failure(format!("Subnet {} is already whitelisted.", subnet)),
+        (true, None) => {
+            state.whitelist_subnets.push(subnet.to_owned());
+            success(format!("Subnet {} is whitelisted successfully.", subnet))
+        }
+        (false, Some(position)) => {
+            state.whitelist_subnets.remove(position);
+            success(format!(
+                "Subnet {} is removed from whitelist successfully.",
+                subnet
+            ))
+        }
+        (false, None) => failure(format!("Subnet {} is not whitelisted.", subnet)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{respond, SimulatorState};
+    use crate::nordvpn::tests::TempDir;
+    use crate::nordvpn::{self, Protocol, Technology};
+    use std::net::IpAddr;
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut state = SimulatorState::default();
+
+        respond(&mut state, &["connect", "Sweden"], "", 0);
+        respond(&mut state, &["set", "dns", "1.1.1.1", "8.8.8.8"], "", 0);
+        respond(
+            &mut state,
+            &["whitelist", "add", "ports", "3000", "5000"],
+            "",
+            0,
+        );
+        respond(
+            &mut state,
+            &["whitelist", "add", "subnet", "10.0.0.0/8"],
+            "",
+            0,
+        );
+
+        assert_eq!(SimulatorState::from_text(&state.to_text()), Some(state));
+    }
+
+    #[test]
+    fn test_status_uptime() {
+        let mut state = SimulatorState::default();
+
+        respond(&mut state, &["connect", "de"], "", 0);
+        let (code, stdout) = respond(&mut state, &["status"], "", 93_784);
+
+        assert_eq!(code, 0);
+        assert!(stdout.contains("Uptime: 1 day 2 hours 3 minutes 4 seconds\n"));
+    }
+
+    #[test]
+    fn test_settings_setters() {
+        let temp_dir = TempDir::new("test_settings_setters");
+
+        let settings = crate::nordvpn::executor::with_executor(temp_dir.simulator(), || {
+            let mut settings = nordvpn::settings().unwrap();
+            settings.set_technology(Technology::OpenVpn).unwrap();
+            settings.set_protocol(Protocol::Tcp).unwrap();
+            settings.set_obfuscate(false).unwrap();
+            settings
+                .set_dns(Some(["1.1.1.1".parse::<IpAddr>().unwrap()]))
+                .unwrap();
+            settings.update().unwrap();
+
+            (settings, nordvpn::settings().unwrap())
+        });
+
+        assert_eq!(settings.0, settings.1);
+    }
+}
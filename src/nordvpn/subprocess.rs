@@ -0,0 +1,190 @@
+use super::executor::{self, Limits, POLL_INTERVAL};
+use super::pool::{self, Completion};
+use gio::{SubprocessFlags, SubprocessLauncher};
+use glib::error::ErrorDomain;
+use glib::translate::ToGlibPtr;
+use glib::{MainContext, Quark};
+use std::future;
+use std::io;
+use std::iter;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus, Output};
+use std::task::Poll;
+use std::time::Instant;
+
+// GLib reports the `errno` of a binary that could not be started in the error
+// domain of `g_spawn`, which the bindings do not cover.
+#[derive(Debug, Clone, Copy)]
+struct ExecError(i32);
+
+impl ErrorDomain for ExecError {
+    fn domain() -> Quark {
+        Quark::from_string("g-exec-error-quark")
+    }
+
+    fn code(self) -> i32 {
+        self.0
+    }
+
+    fn from(code: i32) -> Option<Self> {
+        Some(Self(code))
+    }
+}
+
+// Runs the command with `gio::Subprocess` as a task of `context`, which has to
+// be owned by the calling thread. The returned future can be sent elsewhere.
+pub(crate) fn spawn_local(
+    context: &MainContext,
+    command: Command,
+    input: Option<Vec<u8>>,
+    limits: Limits,
+) -> Completion<io::Result<Output>> {
+    let (completer, completion) = pool::completion(|| {
+        Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "the task was dropped before it completed",
+        ))
+    });
+
+    context.spawn_local(async move {
+        completer.complete(communicate(command, input, limits).await);
+    });
+
+    completion
+}
+
+async fn communicate(
+    command: Command,
+    input: Option<Vec<u8>>,
+    limits: Limits,
+) -> io::Result<Output> {
+    let mut flags = SubprocessFlags::STDOUT_PIPE | SubprocessFlags::STDERR_PIPE;
+
+    if input.is_some() {
+        flags |= SubprocessFlags::STDIN_PIPE;
+    }
+
+    let launcher = SubprocessLauncher::new(flags);
+
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => launcher.setenv(key, value, true),
+            None => launcher.unsetenv(key),
+        }
+    }
+
+    if let Some(directory) = command.get_current_dir() {
+        launcher.set_cwd(directory);
+    }
+
+    let argv = iter::once(command.get_program())
+        .chain(command.get_args())
+        .collect::<Vec<_>>();
+    let subprocess = launcher.spawn(&argv).map_err(spawn_error)?;
+    let input = input.map(glib::Bytes::from_owned);
+    let mut communicated = subprocess.communicate_async_future(input.as_ref());
+
+    let result = if limits.timeout.is_none() && limits.cancel.is_none() {
+        communicated.await
+    } else {
+        let deadline = limits.timeout.map(|timeout| Instant::now() + timeout);
+
+        // The limits are checked on a timer. Once they are reached the process
+        // is killed and the communication dropped, which cancels it, since
+        // anything the process started could keep its pipes open.
+        loop {
+            let mut tick = glib::timeout_future(POLL_INTERVAL);
+            let ready = future::poll_fn(|context| match communicated.as_mut().poll(context) {
+                Poll::Ready(result) => Poll::Ready(Some(result)),
+                Poll::Pending => tick.as_mut().poll(context).map(|()| None),
+            })
+            .await;
+
+            if let Some(result) = ready {
+                break result;
+            } else if let Some(error) = executor::exceeded(&limits, deadline) {
+                subprocess.force_exit();
+                return Err(error);
+            }
+        }
+    };
+
+    let (stdout, stderr) = result.map_err(io::Error::other)?;
+
+    Ok(Output {
+        status: ExitStatus::from_raw(subprocess.status()),
+        stdout: to_vec(stdout),
+        stderr: to_vec(stderr),
+    })
+}
+
+// The data of empty `Bytes` can be a null pointer, which cannot be read as a
+// slice, so their size is checked without reading it first.
+fn to_vec(bytes: Option<glib::Bytes>) -> Vec<u8> {
+    match bytes {
+        Some(bytes) if unsafe { glib::ffi::g_bytes_get_size(bytes.to_glib_none().0) } > 0 => {
+            bytes.to_vec()
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn spawn_error(error: glib::Error) -> io::Error {
+    let kind = match error.kind::<ExecError>() {
+        Some(ExecError(glib::ffi::G_SPAWN_ERROR_NOENT)) => io::ErrorKind::NotFound,
+        Some(ExecError(glib::ffi::G_SPAWN_ERROR_ACCES | glib::ffi::G_SPAWN_ERROR_PERM)) => {
+            io::ErrorKind::PermissionDenied
+        }
+        _ => io::ErrorKind::Other,
+    };
+
+    executor::spawn_error(io::Error::new(kind, error))
+}
+
+// Runs the command with `tokio::process`, from within a Tokio runtime.
+#[cfg(feature = "tokio")]
+pub(crate) async fn run_tokio(
+    command: Command,
+    input: Option<Vec<u8>>,
+    limits: Limits,
+) -> io::Result<Output> {
+    use std::pin::pin;
+    use std::process::Stdio;
+    use tokio::io::AsyncWriteExt;
+
+    let mut child = tokio::process::Command::from(command)
+        .stdin(match input {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(executor::spawn_error)?;
+
+    // As with the blocking executor, a command that exits without reading all
+    // of its input is not an error of its own.
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        tokio::spawn(async move {
+            let _ = stdin.write_all(&input).await;
+        });
+    }
+
+    let mut output = pin!(child.wait_with_output());
+
+    if limits.timeout.is_none() && limits.cancel.is_none() {
+        return output.await;
+    }
+
+    let deadline = limits.timeout.map(|timeout| Instant::now() + timeout);
+
+    // Dropping the output future drops the child, which kills it.
+    loop {
+        if let Ok(output) = tokio::time::timeout(POLL_INTERVAL, output.as_mut()).await {
+            return output;
+        } else if let Some(error) = executor::exceeded(&limits, deadline) {
+            return Err(error);
+        }
+    }
+}
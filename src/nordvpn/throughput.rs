@@ -0,0 +1,227 @@
+use super::cli::{self, CliResult, ConnectionState, Transfer};
+use byte_unit::Byte;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_CAPACITY: usize = 60;
+
+// Bytes per second in each direction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rate {
+    pub received: Byte,
+    pub sent: Byte,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Throughput {
+    pub current: Rate,
+    pub average: Rate,
+    pub peak: Rate,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Interval {
+    elapsed: Duration,
+    received: u128,
+    sent: u128,
+}
+
+// Keeps the transfer between consecutive samples in a ring buffer, rather than
+// the cumulative totals, so that counters starting over after a reconnect only
+// cost the one interval that spans the reset.
+#[derive(Debug, Clone)]
+pub struct ThroughputSampler {
+    capacity: usize,
+    intervals: VecDeque<Interval>,
+    last: Option<(Instant, Transfer)>,
+}
+
+impl Rate {
+    fn per_second(received: u128, sent: u128, elapsed: Duration) -> Self {
+        let seconds = elapsed.as_secs_f64();
+        let rate = |bytes: u128| Byte::from_bytes((bytes as f64 / seconds).round() as u128);
+
+        Self {
+            received: rate(received),
+            sent: rate(sent),
+        }
+    }
+}
+
+impl ThroughputSampler {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            intervals: VecDeque::with_capacity(capacity),
+            last: None,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.intervals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.intervals.clear();
+        self.last = None;
+    }
+
+    // Polls `status()` and records its transfer counters. While not connected
+    // there is nothing to measure, and the next connection starts afresh.
+    pub fn sample(&mut self) -> CliResult<Option<Throughput>> {
+        match cli::status()? {
+            ConnectionState::Connected(status) => {
+                self.push(Instant::now(), status.transfer);
+                Ok(self.throughput())
+            }
+            _ => {
+                self.last = None;
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn push(&mut self, at: Instant, transfer: Transfer) {
+        let last = self.last.replace((at, transfer));
+
+        let (since, previous) = match last {
+            Some((since, previous)) if at > since => (since, previous),
+            _ => return,
+        };
+
+        let received = transfer.received.get_bytes();
+        let sent = transfer.sent.get_bytes();
+
+        if received < previous.received.get_bytes() || sent < previous.sent.get_bytes() {
+            return;
+        }
+
+        if self.intervals.len() == self.capacity {
+            self.intervals.pop_front();
+        }
+
+        self.intervals.push_back(Interval {
+            elapsed: at - since,
+            received: received - previous.received.get_bytes(),
+            sent: sent - previous.sent.get_bytes(),
+        });
+    }
+
+    pub fn current(&self) -> Option<Rate> {
+        let last = self.intervals.back()?;
+
+        Some(Rate::per_second(last.received, last.sent, last.elapsed))
+    }
+
+    pub fn average(&self) -> Option<Rate> {
+        if self.intervals.is_empty() {
+            return None;
+        }
+
+        let (elapsed, received, sent) = self.intervals.iter().fold(
+            (Duration::ZERO, 0, 0),
+            |(elapsed, received, sent), interval| {
+                (
+                    elapsed + interval.elapsed,
+                    received + interval.received,
+                    sent + interval.sent,
+                )
+            },
+        );
+
+        Some(Rate::per_second(received, sent, elapsed))
+    }
+
+    // The peaks of either direction are taken separately, so they may come
+    // from different intervals.
+    pub fn peak(&self) -> Option<Rate> {
+        self.rates().reduce(|peak, rate| Rate {
+            received: peak.received.max(rate.received),
+            sent: peak.sent.max(rate.sent),
+        })
+    }
+
+    pub fn rates(&self) -> impl Iterator<Item = Rate> + '_ {
+        self.intervals
+            .iter()
+            .map(|interval| Rate::per_second(interval.received, interval.sent, interval.elapsed))
+    }
+
+    pub fn throughput(&self) -> Option<Throughput> {
+        Some(Throughput {
+            current: self.current()?,
+            average: self.average()?,
+            peak: self.peak()?,
+        })
+    }
+}
+
+impl Default for ThroughputSampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rate, ThroughputSampler};
+    use crate::nordvpn::Transfer;
+    use byte_unit::Byte;
+    use std::time::{Duration, Instant};
+
+    fn transfer(received: u128, sent: u128) -> Transfer {
+        Transfer {
+            received: Byte::from_bytes(received),
+            sent: Byte::from_bytes(sent),
+        }
+    }
+
+    fn rate(received: u128, sent: u128) -> Rate {
+        Rate {
+            received: Byte::from_bytes(received),
+            sent: Byte::from_bytes(sent),
+        }
+    }
+
+    #[test]
+    fn test_throughput_sampler() {
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        let mut sampler = ThroughputSampler::with_capacity(3);
+
+        sampler.push(at(0), transfer(1_000, 100));
+        assert_eq!(sampler.throughput(), None);
+
+        sampler.push(at(1), transfer(3_000, 300));
+        sampler.push(at(3), transfer(4_000, 1_300));
+
+        let throughput = sampler.throughput().unwrap();
+        assert_eq!(throughput.current, rate(500, 500));
+        assert_eq!(throughput.average, rate(1_000, 400));
+        assert_eq!(throughput.peak, rate(2_000, 500));
+
+        // The counters start over on reconnect, which only drops that interval.
+        sampler.push(at(4), transfer(200, 20));
+        assert_eq!(sampler.len(), 2);
+        assert_eq!(sampler.current(), Some(rate(500, 500)));
+
+        sampler.push(at(5), transfer(1_200, 120));
+        sampler.push(at(6), transfer(1_400, 140));
+        assert_eq!(sampler.len(), 3);
+        assert_eq!(sampler.current(), Some(rate(200, 20)));
+        assert_eq!(sampler.peak(), Some(rate(1_000, 500)));
+        assert_eq!(sampler.average(), Some(rate(550, 280)));
+    }
+}
@@ -0,0 +1,124 @@
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Record<'t> {
+    pub key: &'t str,
+    pub value: &'t str,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Records<'t> {
+    records: Vec<Record<'t>>,
+}
+
+impl<'t> Records<'t> {
+    // Every unindented line starts a record, split on its first colon. Indented
+    // lines continue the previous record, as with the whitelisted ports list.
+    pub fn parse(output: &'t str) -> Self {
+        let mut records: Vec<Record<'t>> = Vec::new();
+        let mut value_start = 0;
+        let mut offset = 0;
+
+        for line in output.split_inclusive('\n') {
+            let start = offset;
+            offset += line.len();
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if line.starts_with([' ', '\t']) {
+                if let Some(record) = records.last_mut() {
+                    record.value = output[value_start..offset].trim();
+                    record.end = offset;
+                    continue;
+                }
+            }
+
+            let (key, value) = line.split_once(':').unwrap_or((line, ""));
+            value_start = offset - value.len();
+
+            records.push(Record {
+                key: key.trim(),
+                value: value.trim(),
+                start,
+                end: offset,
+            });
+        }
+
+        Self { records }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Record<'t>> {
+        self.records.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Record<'t>> {
+        self.records
+            .iter()
+            .find(|record| record.key.eq_ignore_ascii_case(key))
+    }
+
+    pub fn value(&self, key: &str) -> Option<&'t str> {
+        self.get(key).map(|record| record.value)
+    }
+
+    pub fn parse_value<T: FromStr>(&self, key: &str) -> Option<Result<T, T::Err>> {
+        self.value(key).map(str::parse)
+    }
+
+    pub fn enabled(&self, key: &str) -> Option<bool> {
+        match self.value(key)?.split_whitespace().next()? {
+            value if value.eq_ignore_ascii_case("enabled") => Some(true),
+            value if value.eq_ignore_ascii_case("disabled") => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Records;
+
+    #[test]
+    fn test_records() {
+        let output = "Technology: OPENVPN\nFirewall Mark: 0xe1f1\nKill Switch: enabled\nWhitelisted ports:\n\t22 (UDP|TCP)\n\t8000 - 8080 (TCP)\n\nAccount Information:\n";
+        let records = Records::parse(output);
+
+        let keys = records.iter().map(|record| record.key).collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "Technology",
+                "Firewall Mark",
+                "Kill Switch",
+                "Whitelisted ports",
+                "Account Information"
+            ]
+        );
+
+        assert_eq!(records.value("firewall mark"), Some("0xe1f1"));
+        assert_eq!(records.enabled("Kill Switch"), Some(true));
+        assert_eq!(
+            records.value("Whitelisted ports"),
+            Some("22 (UDP|TCP)\n\t8000 - 8080 (TCP)")
+        );
+        assert_eq!(records.parse_value::<u32>("Technology").unwrap().ok(), None);
+
+        let whitelist = records.get("Whitelisted ports").unwrap();
+        assert_eq!(
+            &output[whitelist.start..whitelist.end],
+            "Whitelisted ports:\n\t22 (UDP|TCP)\n\t8000 - 8080 (TCP)\n"
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use super::executor::{self, Executor, Limits};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Output};
+use std::sync::Mutex;
+use std::time::Instant;
+
+pub const REDACTED: &str = "<redacted>";
+
+const SECRET_FLAGS: &[&str] = &["--token", "--password"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptEntry {
+    pub argv: Vec<String>,
+    pub stdout: String,
+    pub stderr: String,
+    pub status: Option<i32>,
+    pub started_at: String,
+    pub elapsed_ms: u64,
+}
+
+impl TranscriptEntry {
+    pub fn args(&self) -> &[String] {
+        self.argv.get(1..).unwrap_or_default()
+    }
+
+    pub fn matches<S: AsRef<str>>(&self, args: &[S]) -> bool {
+        self.args().len() == args.len()
+            && self
+                .args()
+                .iter()
+                .zip(args)
+                .all(|(expected, arg)| expected == REDACTED || expected == arg.as_ref())
+    }
+
+    pub fn to_output(&self) -> Output {
+        Output {
+            status: match self.status {
+                Some(code) => ExitStatus::from_raw(code << 8),
+                None => ExitStatus::from_raw(9),
+            },
+            stdout: self.stdout.clone().into_bytes(),
+            stderr: self.stderr.clone().into_bytes(),
+        }
+    }
+}
+
+pub fn read_transcript<P: AsRef<Path>>(path: P) -> io::Result<Vec<TranscriptEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+
+    reader
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct RecordingExecutor<E: Executor> {
+    inner: E,
+    file: Mutex<File>,
+}
+
+impl<E: Executor> RecordingExecutor<E> {
+    pub fn new<P: AsRef<Path>>(inner: E, path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            inner,
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl<E: Executor> Executor for RecordingExecutor<E> {
+    fn command(&self) -> Command {
+        self.inner.command()
+    }
+
+    fn output(&self, command: &mut Command, limits: &Limits) -> io::Result<Output> {
+        self.record(command, |inner, command| inner.output(command, limits))
+    }
+
+    // The input is left out of the transcript, since it is usually a password.
+    fn output_with_input(
+        &self,
+        command: &mut Command,
+        input: &[u8],
+        limits: &Limits,
+    ) -> io::Result<Output> {
+        self.record(command, |inner, command| {
+            inner.output_with_input(command, input, limits)
+        })
+    }
+}
+
+impl<E: Executor> RecordingExecutor<E> {
+    fn record<F>(&self, command: &mut Command, run: F) -> io::Result<Output>
+    where
+        F: FnOnce(&E, &mut Command) -> io::Result<Output>,
+    {
+        let started_at = Utc::now().to_rfc3339();
+        let started = Instant::now();
+        let output = run(&self.inner, command)?;
+
+        let redacted = redact(command);
+        let argv = std::iter::once(redacted.get_program())
+            .chain(redacted.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+
+        let entry = TranscriptEntry {
+            argv,
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            status: output.status.code(),
+            started_at,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        };
+
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        self.file.lock().unwrap().write_all(line.as_bytes())?;
+
+        Ok(output)
+    }
+}
+
+// A copy of the command with the values of secret flags replaced, for keeping
+// in errors that may be logged.
+pub(crate) fn redact(command: &Command) -> Command {
+    let mut secret = false;
+    let args = command.get_args().map(|arg| {
+        let shown = if secret { OsStr::new(REDACTED) } else { arg };
+        secret = SECRET_FLAGS.iter().any(|flag| arg == *flag);
+        shown
+    });
+
+    executor::copy_command_with_args(command, args.collect::<Vec<_>>())
+}
+
+pub fn record_transcript<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let recording = RecordingExecutor::new(executor::executor(), path)?;
+
+    executor::set_executor(recording);
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct ReplayExecutor {
+    entries: Mutex<Vec<TranscriptEntry>>,
+}
+
+impl ReplayExecutor {
+    pub fn new<I: IntoIterator<Item = TranscriptEntry>>(entries: I) -> Self {
+        Self {
+            entries: Mutex::new(entries.into_iter().collect()),
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(read_transcript(path)?))
+    }
+
+    pub fn remaining(&self) -> Vec<TranscriptEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+}
+
+impl Executor for ReplayExecutor {
+    fn output(&self, command: &mut Command, _limits: &Limits) -> io::Result<Output> {
+        let args = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.iter().position(|entry| entry.matches(&args)) {
+            Some(position) => Ok(entries.remove(position).to_output()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no transcript entry left for arguments {:?}", args),
+            )),
+        }
+    }
+
+    fn output_with_input(
+        &self,
+        command: &mut Command,
+        _input: &[u8],
+        limits: &Limits,
+    ) -> io::Result<Output> {
+        self.output(command, limits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_transcript, Limits, RecordingExecutor, ReplayExecutor};
+    use crate::nordvpn::executor::with_executor;
+    use crate::nordvpn::simulator::Simulator;
+    use crate::nordvpn::tests::TempDir;
+    use crate::nordvpn::{self, Executor};
+    use std::io;
+
+    #[test]
+    fn test_record_and_replay() {
+        let temp_dir = TempDir::new("test_record_and_replay");
+
+        let path = temp_dir.path().join("transcript.jsonl");
+        let recording =
+            RecordingExecutor::new(Simulator::new(temp_dir.path().join("state")), &path).unwrap();
+
+        let recorded = with_executor(recording, || {
+            nordvpn::connect(None).unwrap();
+            (nordvpn::status().unwrap(), nordvpn::settings().unwrap())
+        });
+
+        let entries = read_transcript(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].args(), ["status"]);
+
+        let replay = ReplayExecutor::new(entries);
+        let replayed = with_executor(ReplayExecutor::new(replay.remaining()), || {
+            (nordvpn::status().unwrap(), nordvpn::settings().unwrap())
+        });
+
+        assert_eq!(recorded, replayed);
+
+        let error = replay.output(replay.command().arg("account"), &Limits::default());
+        assert_eq!(error.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,148 @@
+use chrono::{DateTime, TimeZone};
+use std::fmt;
+use std::time::Duration;
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+const MONTH: u64 = 30 * DAY;
+const YEAR: u64 = 365 * DAY;
+
+// The components as the CLI printed them. The daemon formats a plain duration,
+// so a month is always 30 days and a year 365, which keeps every conversion
+// exact in whole seconds.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Uptime {
+    pub years: u64,
+    pub months: u64,
+    pub days: u64,
+    pub hours: u64,
+    pub minutes: u64,
+    pub seconds: u64,
+}
+
+impl Uptime {
+    pub const ZERO: Self = Self {
+        years: 0,
+        months: 0,
+        days: 0,
+        hours: 0,
+        minutes: 0,
+        seconds: 0,
+    };
+
+    pub fn from_secs(seconds: u64) -> Self {
+        Self {
+            years: seconds / YEAR,
+            months: seconds % YEAR / MONTH,
+            days: seconds % YEAR % MONTH / DAY,
+            hours: seconds % DAY / HOUR,
+            minutes: seconds % HOUR / MINUTE,
+            seconds: seconds % MINUTE,
+        }
+    }
+
+    pub fn as_secs(&self) -> u64 {
+        self.years * YEAR
+            + self.months * MONTH
+            + self.days * DAY
+            + self.hours * HOUR
+            + self.minutes * MINUTE
+            + self.seconds
+    }
+
+    pub fn to_duration(&self) -> Duration {
+        Duration::from_secs(self.as_secs())
+    }
+
+    // Carries overflowing components, such as 90 minutes, into larger units.
+    pub fn normalized(&self) -> Self {
+        Self::from_secs(self.as_secs())
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.as_secs() == 0
+    }
+
+    pub fn started_at<Tz: TimeZone>(&self, now: DateTime<Tz>) -> DateTime<Tz> {
+        now - chrono::Duration::seconds(self.as_secs() as i64)
+    }
+}
+
+impl From<Duration> for Uptime {
+    fn from(duration: Duration) -> Self {
+        Self::from_secs(duration.as_secs())
+    }
+}
+
+impl From<Uptime> for Duration {
+    fn from(uptime: Uptime) -> Self {
+        uptime.to_duration()
+    }
+}
+
+// Reads like the CLI, "1 day 3 hours 5 minutes", leaving out zero components.
+impl fmt::Display for Uptime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let components = [
+            (self.years, "year"),
+            (self.months, "month"),
+            (self.days, "day"),
+            (self.hours, "hour"),
+            (self.minutes, "minute"),
+            (self.seconds, "second"),
+        ];
+        let mut separator = "";
+
+        for (value, unit) in components.into_iter().filter(|(value, _)| *value != 0) {
+            write!(f, "{}{} {}", separator, value, unit)?;
+            if value != 1 {
+                write!(f, "s")?;
+            }
+            separator = " ";
+        }
+
+        if separator.is_empty() {
+            write!(f, "0 seconds")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Uptime;
+    use chrono::{TimeZone, Utc};
+    use std::time::Duration;
+
+    #[test]
+    fn test_uptime() {
+        let uptime = Uptime {
+            days: 1,
+            hours: 3,
+            minutes: 5,
+            ..Uptime::ZERO
+        };
+
+        assert_eq!(uptime.as_secs(), 97_500);
+        assert_eq!(uptime.to_string(), "1 day 3 hours 5 minutes");
+        assert_eq!(Uptime::from(uptime.to_duration()), uptime);
+
+        let now = Utc.with_ymd_and_hms(2023, 3, 5, 12, 0, 0).unwrap();
+        assert_eq!(
+            uptime.started_at(now),
+            Utc.with_ymd_and_hms(2023, 3, 4, 8, 55, 0).unwrap()
+        );
+
+        let long = Uptime::from(Duration::from_secs(400 * 86_400 + 61));
+        assert_eq!(long.to_string(), "1 year 1 month 5 days 1 minute 1 second");
+        assert_eq!(Uptime::ZERO.to_string(), "0 seconds");
+
+        let overflowing = Uptime {
+            minutes: 90,
+            ..Uptime::ZERO
+        };
+        assert_eq!(overflowing.normalized().to_string(), "1 hour 30 minutes");
+    }
+}
@@ -0,0 +1,280 @@
+use super::history::Session;
+use byte_unit::Byte;
+use chrono::{DateTime, Datelike, Months, NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+pub const DEFAULT_THRESHOLDS: &[u8] = &[50, 75, 90, 100];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, strum::Display, strum::EnumString)]
+#[strum(ascii_case_insensitive)]
+#[strum(serialize_all = "lowercase")]
+pub enum Grouping {
+    Day,
+    Week,
+    Month,
+    Country,
+}
+
+// Totals in bytes, like those of `Session`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct UsageRow {
+    pub key: String,
+    pub sessions: usize,
+    pub received: u64,
+    pub sent: u64,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataCap {
+    pub limit: u64,
+    pub reset_day: u32,
+    pub thresholds: Vec<u8>,
+    warned: Option<(NaiveDate, u8)>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CapWarning {
+    pub threshold: u8,
+    pub used: u64,
+    pub limit: u64,
+}
+
+// Sessions count towards the period they started in, by the time in `tz`
+// (usually `Local`), and are not split at midnight or at the end of a month.
+pub fn usage<Tz: TimeZone>(sessions: &[Session], grouping: Grouping, tz: &Tz) -> Vec<UsageRow> {
+    let mut rows = BTreeMap::<String, UsageRow>::new();
+
+    for session in sessions {
+        let date = local_date(session.started_at, tz);
+        let key = match grouping {
+            Grouping::Day => date.format("%Y-%m-%d").to_string(),
+            Grouping::Week => {
+                let week = date.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+            Grouping::Month => date.format("%Y-%m").to_string(),
+            Grouping::Country => session.country.clone(),
+        };
+
+        let row = rows.entry(key.clone()).or_insert_with(|| UsageRow {
+            key,
+            ..UsageRow::default()
+        });
+        row.sessions += 1;
+        row.received += session.received;
+        row.sent += session.sent;
+        row.total += session.received + session.sent;
+    }
+
+    rows.into_values().collect()
+}
+
+pub fn to_csv(rows: &[UsageRow]) -> String {
+    let mut csv = String::from("key,sessions,received,sent,total\n");
+
+    for row in rows {
+        let key = if row.key.contains([',', '"', '\n']) {
+            format!("\"{}\"", row.key.replace('"', "\"\""))
+        } else {
+            row.key.clone()
+        };
+
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            key, row.sessions, row.received, row.sent, row.total
+        ));
+    }
+
+    csv
+}
+
+pub fn to_json(rows: &[UsageRow]) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(rows)
+}
+
+fn local_date<Tz: TimeZone>(at: DateTime<Utc>, tz: &Tz) -> NaiveDate {
+    at.with_timezone(tz).date_naive()
+}
+
+impl CapWarning {
+    pub fn is_exceeded(&self) -> bool {
+        self.used >= self.limit
+    }
+}
+
+impl DataCap {
+    pub fn new(limit: Byte) -> Self {
+        Self {
+            limit: limit.get_bytes() as u64,
+            reset_day: 1,
+            thresholds: DEFAULT_THRESHOLDS.to_vec(),
+            warned: None,
+        }
+    }
+
+    // Days past the 28th would not exist in every month.
+    pub fn reset_day(mut self, day: u32) -> Self {
+        self.reset_day = day.clamp(1, 28);
+        self
+    }
+
+    pub fn thresholds<I: IntoIterator<Item = u8>>(mut self, thresholds: I) -> Self {
+        self.thresholds = thresholds.into_iter().collect();
+        self.thresholds.sort_unstable();
+        self.thresholds.dedup();
+        self
+    }
+
+    pub fn period_start<Tz: TimeZone>(&self, now: DateTime<Utc>, tz: &Tz) -> NaiveDate {
+        let today = local_date(now, tz);
+        let start = today.with_day(self.reset_day).unwrap();
+
+        if today.day() >= self.reset_day {
+            start
+        } else {
+            start - Months::new(1)
+        }
+    }
+
+    pub fn used<Tz: TimeZone>(&self, sessions: &[Session], now: DateTime<Utc>, tz: &Tz) -> u64 {
+        let period_start = self.period_start(now, tz);
+
+        sessions
+            .iter()
+            .filter(|session| local_date(session.started_at, tz) >= period_start)
+            .map(|session| session.received + session.sent)
+            .sum()
+    }
+
+    // Warns once for the highest threshold crossed since the last warning, and
+    // starts over when a new billing period begins.
+    pub fn check<Tz: TimeZone>(
+        &mut self,
+        sessions: &[Session],
+        now: DateTime<Utc>,
+        tz: &Tz,
+    ) -> Option<CapWarning> {
+        let period_start = self.period_start(now, tz);
+        let used = self.used(sessions, now, tz);
+        let crossed = self
+            .thresholds
+            .iter()
+            .copied()
+            .filter(|&threshold| used as u128 * 100 >= self.limit as u128 * threshold as u128)
+            .max()?;
+
+        match self.warned {
+            Some((period, warned)) if period == period_start && warned >= crossed => None,
+            _ => {
+                self.warned = Some((period_start, crossed));
+
+                Some(CapWarning {
+                    threshold: crossed,
+                    used,
+                    limit: self.limit,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_csv, usage, DataCap, Grouping};
+    use crate::nordvpn::{Protocol, Session, Technology};
+    use byte_unit::Byte;
+    use chrono::{DateTime, Duration, FixedOffset, NaiveDate, Utc};
+
+    // Noon in `tz()`, so that the dates are the same whatever the host's zone.
+    fn at(date: &str) -> DateTime<Utc> {
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+        date.and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_local_timezone(tz())
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn tz() -> FixedOffset {
+        FixedOffset::east_opt(10 * 3600).unwrap()
+    }
+
+    fn session(date: &str, country: &str, received: u64, sent: u64) -> Session {
+        Session {
+            started_at: at(date),
+            ended_at: Some(at(date) + Duration::hours(1)),
+            hostname: "us8412.nordvpn.com".to_owned(),
+            country: country.to_owned(),
+            city: String::new(),
+            technology: Technology::NordLynx,
+            protocol: Protocol::Udp,
+            received,
+            sent,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn test_usage() {
+        let sessions = [
+            session("2023-02-27", "United States", 100, 10),
+            session("2023-03-01", "Germany", 200, 20),
+            session("2023-03-01", "Korea, Republic of", 300, 30),
+        ];
+
+        let days = usage(&sessions, Grouping::Day, &tz());
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[1].key, "2023-03-01");
+        assert_eq!((days[1].sessions, days[1].total), (2, 550));
+
+        let weeks = usage(&sessions, Grouping::Week, &tz());
+        assert_eq!(weeks.len(), 1);
+        assert_eq!(weeks[0].key, "2023-W09");
+
+        let months = usage(&sessions, Grouping::Month, &tz());
+        let keys = months
+            .iter()
+            .map(|row| row.key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, ["2023-02", "2023-03"]);
+
+        let countries = usage(&sessions, Grouping::Country, &tz());
+        assert_eq!(
+            to_csv(&countries),
+            "key,sessions,received,sent,total\nGermany,1,200,20,220\n\"Korea, Republic of\",1,300,30,330\nUnited States,1,100,10,110\n"
+        );
+    }
+
+    #[test]
+    fn test_data_cap() {
+        let mut cap = DataCap::new(Byte::from_bytes(1_000)).reset_day(28);
+        let mut sessions = vec![session("2023-02-27", "Germany", 400, 100)];
+
+        assert_eq!(
+            cap.period_start(at("2023-03-05"), &tz()),
+            NaiveDate::from_ymd_opt(2023, 2, 28).unwrap()
+        );
+        assert_eq!(cap.check(&sessions, at("2023-03-05"), &tz()), None);
+
+        sessions.push(session("2023-03-01", "Germany", 450, 50));
+        let warning = cap.check(&sessions, at("2023-03-05"), &tz()).unwrap();
+        assert_eq!((warning.threshold, warning.used), (50, 500));
+        assert_eq!(cap.check(&sessions, at("2023-03-06"), &tz()), None);
+
+        sessions.push(session("2023-03-02", "Germany", 500, 0));
+        let warning = cap.check(&sessions, at("2023-03-06"), &tz()).unwrap();
+        assert_eq!(warning.threshold, 100);
+        assert!(warning.is_exceeded());
+
+        assert_eq!(cap.check(&sessions, at("2023-03-29"), &tz()), None);
+        sessions.push(session("2023-03-29", "Germany", 600, 0));
+        assert_eq!(
+            cap.check(&sessions, at("2023-03-29"), &tz())
+                .unwrap()
+                .threshold,
+            50
+        );
+    }
+}
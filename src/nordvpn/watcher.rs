@@ -0,0 +1,281 @@
+use super::cli::{self, CliError, ConnectionState, PartialStatus, Settings, Status};
+use super::cli::{Technology, Transfer};
+use super::executor::{self, CancelHandle, Limits};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub const MIN_INTERVAL: Duration = Duration::from_secs(1);
+pub const MAX_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone)]
+pub enum StatusEvent {
+    Connected(Status),
+    Connecting(PartialStatus),
+    Reconnecting(PartialStatus),
+    Disconnected,
+    ServerChanged(Status),
+    TechnologyChanged(Technology),
+    SettingsChanged(Settings),
+    TransferUpdated(Transfer),
+    Error(Arc<CliError>),
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    subscribers: Vec<Sender<StatusEvent>>,
+    state: Option<ConnectionState>,
+    settings: Option<Settings>,
+    stopped: bool,
+}
+
+impl Shared {
+    // Nothing is published once the watcher has been dropped, even if a poll
+    // was already underway when the stop signal arrived.
+    fn publish(&mut self, event: StatusEvent) {
+        if self.stopped {
+            return;
+        }
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+// Polls `status()` and `settings()` on a background thread, starting over at
+// the minimum interval whenever something changes and doubling it up to the
+// maximum while nothing does. Dropping the watcher stops the thread.
+#[derive(Debug)]
+pub struct StatusWatcher {
+    shared: Arc<Mutex<Shared>>,
+    stop: Option<Sender<()>>,
+    cancel: CancelHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StatusWatcher {
+    pub fn spawn() -> Self {
+        Self::with_intervals(MIN_INTERVAL, MAX_INTERVAL)
+    }
+
+    pub fn with_intervals(min: Duration, max: Duration) -> Self {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let (stop, stopped) = mpsc::channel();
+        let cancel = CancelHandle::new();
+        let limits = Limits {
+            cancel: Some(cancel.clone()),
+            ..executor::limits()
+        };
+
+        let thread = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                executor::with_limits(limits, || watch(&shared, stopped, min, max))
+            })
+        };
+
+        Self {
+            shared,
+            stop: Some(stop),
+            cancel,
+            thread: Some(thread),
+        }
+    }
+
+    // New subscribers are first told the last known state and settings, so
+    // they never have to poll for the initial values themselves.
+    pub fn subscribe(&self) -> Receiver<StatusEvent> {
+        let (sender, receiver) = mpsc::channel();
+        let mut shared = self.shared.lock().unwrap();
+
+        if let Some(event) = shared.state.as_ref().and_then(state_event) {
+            let _ = sender.send(event);
+        }
+        if let Some(settings) = &shared.settings {
+            let _ = sender.send(StatusEvent::SettingsChanged(settings.clone()));
+        }
+
+        shared.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn state(&self) -> Option<ConnectionState> {
+        self.shared.lock().unwrap().state.clone()
+    }
+
+    pub fn settings(&self) -> Option<Settings> {
+        self.shared.lock().unwrap().settings.clone()
+    }
+}
+
+impl Drop for StatusWatcher {
+    fn drop(&mut self) {
+        self.shared.lock().unwrap().stopped = true;
+        self.stop.take();
+        self.cancel.cancel();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn watch(shared: &Mutex<Shared>, stopped: Receiver<()>, min: Duration, max: Duration) {
+    let mut interval = min;
+
+    loop {
+        let changed = match (cli::status(), cli::settings()) {
+            (Ok(state), Ok(settings)) => {
+                let mut shared = shared.lock().unwrap();
+                let changed = update(&mut shared, state, settings);
+
+                match shared.state {
+                    Some(ConnectionState::Connecting(_) | ConnectionState::Reconnecting(_)) => true,
+                    _ => changed,
+                }
+            }
+            (Err(error), _) | (_, Err(error)) => {
+                if matches!(error, CliError::Cancelled(_)) {
+                    return;
+                }
+
+                shared
+                    .lock()
+                    .unwrap()
+                    .publish(StatusEvent::Error(Arc::new(error)));
+                interval = max;
+                false
+            }
+        };
+
+        if changed {
+            interval = min;
+        }
+
+        match stopped.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => interval = (interval * 2).min(max),
+            _ => return,
+        }
+    }
+}
+
+// Publishes the differences against the previous poll and returns whether
+// anything other than the transfer counters changed.
+fn update(shared: &mut Shared, state: ConnectionState, settings: Settings) -> bool {
+    let mut events = Vec::new();
+    let mut changed = false;
+
+    match (&shared.state, &state) {
+        (Some(ConnectionState::Connected(previous)), ConnectionState::Connected(current)) => {
+            if previous.hostname != current.hostname {
+                events.push(StatusEvent::ServerChanged(current.clone()));
+                changed = true;
+            }
+            if previous.transfer != current.transfer {
+                events.push(StatusEvent::TransferUpdated(current.transfer));
+            }
+        }
+        (previous, current) => {
+            let variant = |state: &ConnectionState| std::mem::discriminant(state);
+
+            if previous.as_ref().map(variant) != Some(variant(current)) {
+                events.extend(state_event(current));
+                changed = true;
+            }
+        }
+    }
+
+    match &shared.settings {
+        Some(previous) if *previous == settings => {}
+        previous => {
+            if previous
+                .as_ref()
+                .is_some_and(|previous| previous.technology != settings.technology)
+            {
+                events.push(StatusEvent::TechnologyChanged(settings.technology));
+            }
+            events.push(StatusEvent::SettingsChanged(settings.clone()));
+            changed = true;
+        }
+    }
+
+    shared.state = Some(state);
+    shared.settings = Some(settings);
+
+    for event in events {
+        shared.publish(event);
+    }
+
+    changed
+}
+
+fn state_event(state: &ConnectionState) -> Option<StatusEvent> {
+    match state {
+        ConnectionState::Disconnected => Some(StatusEvent::Disconnected),
+        ConnectionState::Connecting(partial) => Some(StatusEvent::Connecting(partial.clone())),
+        ConnectionState::Connected(status) => Some(StatusEvent::Connected(status.clone())),
+        ConnectionState::Reconnecting(partial) => Some(StatusEvent::Reconnecting(partial.clone())),
+        ConnectionState::Unknown(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StatusEvent, StatusWatcher};
+    use crate::nordvpn::executor::with_executor;
+    use crate::nordvpn::tests::TempDir;
+    use crate::nordvpn::{self, ConnectOption, Technology};
+    use std::sync::mpsc::Receiver;
+    use std::time::Duration;
+
+    // Skips transfer updates, which arrive whenever the simulated counters move.
+    fn next(events: &Receiver<StatusEvent>) -> StatusEvent {
+        loop {
+            match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+                StatusEvent::TransferUpdated(_) => continue,
+                event => return event,
+            }
+        }
+    }
+
+    #[test]
+    fn test_status_watcher() {
+        let temp_dir = TempDir::new("test_status_watcher");
+
+        with_executor(temp_dir.simulator(), || {
+            let watcher =
+                StatusWatcher::with_intervals(Duration::from_millis(10), Duration::from_millis(40));
+            let events = watcher.subscribe();
+
+            assert!(matches!(next(&events), StatusEvent::Disconnected));
+            assert!(matches!(next(&events), StatusEvent::SettingsChanged(_)));
+
+            nordvpn::connect(None).unwrap();
+            assert!(matches!(next(&events), StatusEvent::Connected(_)));
+
+            let late = watcher.subscribe();
+            assert!(matches!(next(&late), StatusEvent::Connected(_)));
+            assert!(matches!(next(&late), StatusEvent::SettingsChanged(_)));
+
+            nordvpn::connect(Some(&ConnectOption::CountryCode("de".to_owned()))).unwrap();
+            match next(&events) {
+                StatusEvent::ServerChanged(status) => assert_eq!(status.country, "Germany"),
+                event => panic!("unexpected event {:?}", event),
+            }
+
+            let mut settings = nordvpn::settings().unwrap();
+            settings.set_technology(Technology::OpenVpn).unwrap();
+            assert!(matches!(
+                next(&events),
+                StatusEvent::TechnologyChanged(Technology::OpenVpn)
+            ));
+            assert!(matches!(next(&events), StatusEvent::SettingsChanged(_)));
+
+            nordvpn::disconnect().unwrap();
+            assert!(matches!(next(&events), StatusEvent::Disconnected));
+
+            drop(watcher);
+            assert!(events.recv().is_err());
+        });
+    }
+}
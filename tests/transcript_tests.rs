@@ -0,0 +1,41 @@
+use nordvpn_viking::nordvpn::{self, ReplayExecutor};
+use semver::Version;
+
+// The transcript was recorded from the simulator set to version 3.12.3, not
+// from a real client, so its counters, addresses and timings are made up.
+#[test]
+fn test_replay_simulated_3_12_3() {
+    nordvpn::set_executor(
+        ReplayExecutor::from_path("tests/transcripts/simulated-3.12.3.jsonl").unwrap(),
+    );
+
+    assert_eq!(nordvpn::version().unwrap(), Version::new(3, 12, 3));
+
+    let account = nordvpn::account().unwrap().unwrap();
+    assert_eq!(account.email, "user@example.com");
+    assert!(account.active);
+
+    assert_eq!(
+        nordvpn::status().unwrap(),
+        nordvpn::ConnectionState::Disconnected
+    );
+
+    let connected =
+        nordvpn::connect(Some(&nordvpn::ConnectOption::Country("Sweden".to_owned()))).unwrap();
+    assert_eq!(connected.hostname, "se497.nordvpn.com");
+    assert_eq!(connected.server, 497);
+
+    let status = nordvpn::status().unwrap().into_status().unwrap();
+    assert_eq!(status.hostname, connected.hostname);
+    assert_eq!(status.city, "Stockholm");
+
+    let settings = nordvpn::settings().unwrap();
+    assert_eq!(settings.technology, nordvpn::Technology::NordLynx);
+    assert_eq!(settings.dns, None);
+
+    assert!(nordvpn::disconnect().unwrap());
+    assert_eq!(
+        nordvpn::status().unwrap(),
+        nordvpn::ConnectionState::Disconnected
+    );
+}